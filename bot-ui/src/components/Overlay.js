@@ -66,7 +66,7 @@ class CurrentSong extends React.Component {
     }
 
     let progressBarStyle = {
-      width: `${percentage(this.props.elapsed, this.props.duration)}%`,
+      width: this.props.durationKnown ? `${percentage(this.props.elapsed, this.props.duration)}%` : "0%",
     };
 
     let stateClasses = "state";
@@ -145,6 +145,7 @@ export default class Overlay extends React.Component {
       albumArt: null,
       elapsed: 0,
       duration: 0,
+      durationKnown: false,
       isPlaying: false,
     };
   }
@@ -165,6 +166,7 @@ export default class Overlay extends React.Component {
           requestBy: data.user,
           elapsed: data.elapsed,
           duration: data.duration,
+          durationKnown: data.duration_known,
           isPlaying: data.is_playing,
           source: null,
         };
@@ -207,6 +209,14 @@ export default class Overlay extends React.Component {
         this.setState({
           elapsed: data.elapsed,
           duration: data.duration,
+          durationKnown: data.duration_known,
+        });
+
+        break;
+      case "song/state":
+        this.setState({
+          elapsed: data.elapsed,
+          isPlaying: data.is_playing,
         });
 
         break;
@@ -225,6 +235,7 @@ export default class Overlay extends React.Component {
           albumArt={this.state.albumArt}
           elapsed={this.state.elapsed}
           duration={this.state.duration}
+          durationKnown={this.state.durationKnown}
           source={this.state.source}
           isPlaying={this.state.isPlaying}
         />
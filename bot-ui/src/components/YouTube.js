@@ -68,6 +68,14 @@ export default class YouTube extends React.Component {
             }
 
             this.setState(update);
+            break;
+          case "load":
+            if (this.state.videoId !== data.event.video_id) {
+              let videoId = data.event.video_id;
+              this.player.cueVideoById({videoId, suggestedQuality: SUGGESTED_QUALITY});
+              this.setState({ playing: false, stopped: false, videoId });
+            }
+
             break;
           case "pause":
             if (this.state.playing) {
@@ -0,0 +1 @@
+ALTER TABLE commands ADD COLUMN delay INTEGER DEFAULT NULL;
\ No newline at end of file
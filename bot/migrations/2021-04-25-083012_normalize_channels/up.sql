@@ -0,0 +1,11 @@
+UPDATE OR IGNORE commands
+SET channel = CASE WHEN channel LIKE '#%' THEN lower(channel) ELSE '#' || lower(channel) END;
+
+UPDATE OR IGNORE aliases
+SET channel = CASE WHEN channel LIKE '#%' THEN lower(channel) ELSE '#' || lower(channel) END;
+
+UPDATE OR IGNORE promotions
+SET channel = CASE WHEN channel LIKE '#%' THEN lower(channel) ELSE '#' || lower(channel) END;
+
+UPDATE OR IGNORE themes
+SET channel = CASE WHEN channel LIKE '#%' THEN lower(channel) ELSE '#' || lower(channel) END;
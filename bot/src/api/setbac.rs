@@ -127,7 +127,11 @@ struct RemoteBuilder {
 impl RemoteBuilder {
     async fn init(&self, remote: &mut Remote) {
         if self.enabled {
-            remote.rx = Some(self.global_bus.subscribe());
+            // Only update on switches to current song.
+            remote.rx = Some(self.global_bus.subscribe_filter_map(|m| match m {
+                bus::Global::SongModified => Some(()),
+                _ => None,
+            }));
 
             remote.player = match self.player.as_ref() {
                 Some(player) => Some(player.clone()),
@@ -159,7 +163,7 @@ impl RemoteBuilder {
 
 #[derive(Default)]
 struct Remote {
-    rx: Option<bus::Reader<bus::Global>>,
+    rx: Option<BoxStream<'static, ()>>,
     player: Option<player::Player>,
     setbac: Option<Setbac>,
 }
@@ -232,15 +236,7 @@ pub async fn run(
                     remote_builder.enabled = enabled;
                     remote_builder.init(&mut remote).await;
                 }
-                event = async { remote.rx.as_mut().unwrap().recv().await }, if remote.rx.is_some() => {
-                    let event = event?;
-
-                    // Only update on switches to current song.
-                    match event {
-                        bus::Global::SongModified => (),
-                        _ => continue,
-                    };
-
+                Some(()) = async { remote.rx.as_mut().unwrap().next().await }, if remote.rx.is_some() => {
                     let setbac = match remote.setbac.as_ref() {
                         Some(setbac) => setbac,
                         None => continue,
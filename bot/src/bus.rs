@@ -1,26 +1,733 @@
 use crate::player;
+use crate::prelude::BoxStream;
+use crate::task;
 use crate::track_id::TrackId;
-use std::collections::HashMap;
+use parking_lot::Mutex;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
 use tokio::sync::broadcast;
-use tokio::sync::RwLock;
+use tokio::sync::mpsc;
+use tokio::sync::watch;
+
+/// Number of normal priority messages buffered for each reader.
+const CAPACITY: usize = 64;
+/// Number of high priority messages buffered for each reader.
+const HIGH_CAPACITY: usize = 16;
+/// How long to wait for lagging readers before sending a reliable message.
+const RELIABLE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long clients are asked to wait before reconnecting to an idle bus.
+const RETRY_AFTER_BASE: Duration = Duration::from_secs(1);
+/// How much longer clients are asked to wait for every reader of the bus.
+const RETRY_AFTER_PER_READER: Duration = Duration::from_millis(50);
+/// The longest clients are asked to wait before reconnecting, before jitter.
+const RETRY_AFTER_MAX: Duration = Duration::from_secs(30);
+
+/// Errors raised by the bus.
+#[derive(Debug, Error)]
+pub enum BusError {
+    /// The bus has been shut down.
+    #[error("bus is closed")]
+    Closed,
+    /// The reader lagged behind, missing the given number of messages.
+    #[error("bus reader lagged behind by {0} messages")]
+    Lagged(u64),
+    /// A message could not be serialized.
+    #[error("failed to serialize bus message")]
+    Serialize(#[from] serde_json::Error),
+    /// A received message could not be deserialized.
+    #[error("failed to deserialize bus message")]
+    Deserialize(#[source] serde_json::Error),
+    /// A received frame is not framed as expected.
+    #[error("malformed bus frame")]
+    Frame,
+    /// Readers were still lagging behind when a send timed out.
+    #[error("bus is full")]
+    Full,
+}
+
+impl From<broadcast::error::RecvError> for BusError {
+    fn from(error: broadcast::error::RecvError) -> Self {
+        match error {
+            broadcast::error::RecvError::Closed => Self::Closed,
+            broadcast::error::RecvError::Lagged(n) => Self::Lagged(n),
+        }
+    }
+}
+
+/// The priority of a bussed message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Normal,
+    /// High priority messages are delivered through a separate channel so
+    /// that they are not crowded out by a flood of normal messages.
+    High,
+}
+
+/// The key under which a bussed message is cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageId {
+    YouTubeCurrent,
+    YouTubeVolume,
+    SongProgress,
+    SongCurrent,
+    Volume,
+    Enabled,
+    Device,
+    Health,
+    /// A custom key, used by [Raw] messages.
+    Custom(&'static str),
+}
+
+impl MessageId {
+    /// Get the identifier as a string.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::YouTubeCurrent => "youtube/current",
+            Self::YouTubeVolume => "youtube/volume",
+            Self::SongProgress => "song/progress",
+            Self::SongCurrent => "song/current",
+            Self::Volume => "volume",
+            Self::Enabled => "enabled",
+            Self::Device => "device",
+            Self::Health => "health",
+            Self::Custom(id) => id,
+        }
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_str().fmt(fmt)
+    }
+}
 
 pub trait Message: 'static + Clone + Send + Sync + serde::Serialize {
     /// The ID of a bussed message.
-    fn id(&self) -> Option<&'static str> {
+    fn id(&self) -> Option<MessageId> {
+        None
+    }
+
+    /// The priority of a bussed message.
+    fn priority(&self) -> Priority {
+        Priority::Normal
+    }
+
+    /// Whether the message must reach every reader.
+    ///
+    /// Before a reliable message is sent, the bus waits briefly for readers
+    /// which are lagging behind to catch up so that it isn't skipped by
+    /// them. This delays sending it by up to a second while the bus is under
+    /// pressure.
+    fn reliable(&self) -> bool {
+        false
+    }
+}
+
+/// A receiver of the bus.
+pub struct Reader<T> {
+    normal: broadcast::Receiver<(u64, T)>,
+    high: broadcast::Receiver<(u64, T)>,
+    /// Sequence number up to which every message has been received.
+    resume_seq: u64,
+    /// Sequence numbers received ahead of `resume_seq`.
+    ahead: BTreeSet<u64>,
+    /// Signalled when the bus is shut down.
+    shutdown: watch::Receiver<bool>,
+    /// Whether the last receive lagged.
+    lagging: bool,
+    /// Number of lagging readers, shared with the bus.
+    lagging_count: Arc<AtomicUsize>,
+    /// Signalled when the number of lagging readers changes.
+    lagging_changed: Arc<watch::Sender<()>>,
+    /// Number of messages missed by lagging readers, shared with the bus.
+    dropped: Arc<AtomicU64>,
+}
+
+impl<T> Reader<T>
+where
+    T: Clone,
+{
+    /// Receive the next message from the bus.
+    ///
+    /// High priority messages which are already queued are always received
+    /// before any normal ones.
+    pub async fn recv(&mut self) -> Result<T, BusError> {
+        Ok(self.recv_seq().await?.1)
+    }
+
+    /// Receive the next message from the bus together with its sequence
+    /// number.
+    ///
+    /// Since high priority messages skip ahead, sequence numbers are not
+    /// received in order. See [resume_seq][Reader::resume_seq] for where to
+    /// resume from.
+    pub async fn recv_seq(&mut self) -> Result<(u64, T), BusError> {
+        let result = self.recv_inner().await;
+
+        match &result {
+            Ok((seq, _)) => self.received(*seq),
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                self.dropped.fetch_add(*n, Ordering::Relaxed);
+            }
+            Err(..) => (),
+        }
+
+        self.set_lagging(matches!(
+            result,
+            Err(broadcast::error::RecvError::Lagged(..))
+        ));
+        Ok(result?)
+    }
+
+    /// The sequence number up to which every message has been received.
+    ///
+    /// Replaying every message after it catches up a client without skipping
+    /// any normal priority messages which were overtaken by a high priority
+    /// one, at the cost of replaying the high priority message again.
+    pub fn resume_seq(&self) -> u64 {
+        self.resume_seq
+    }
+
+    async fn recv_inner(&mut self) -> Result<(u64, T), broadcast::error::RecvError> {
+        use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+        if *self.shutdown.borrow() {
+            return Err(RecvError::Closed);
+        }
+
+        match self.high.try_recv() {
+            Ok(m) => return Ok(m),
+            Err(TryRecvError::Lagged(n)) => return Err(RecvError::Lagged(n)),
+            Err(TryRecvError::Closed) => return Err(RecvError::Closed),
+            Err(TryRecvError::Empty) => (),
+        }
+
+        let Self {
+            normal,
+            high,
+            shutdown,
+            ..
+        } = self;
+
+        tokio::select! {
+            m = high.recv() => m,
+            m = normal.recv() => m,
+            _ = shutdown.changed() => Err(RecvError::Closed),
+        }
+    }
+}
+
+impl<T> Reader<T> {
+    /// Record that the message with the given sequence number was received.
+    fn received(&mut self, seq: u64) {
+        if seq != self.resume_seq + 1 {
+            self.ahead.insert(seq);
+            return;
+        }
+
+        self.resume_seq = seq;
+
+        while self.ahead.remove(&(self.resume_seq + 1)) {
+            self.resume_seq += 1;
+        }
+    }
+
+    /// Update whether this reader is lagging.
+    fn set_lagging(&mut self, lagging: bool) {
+        if self.lagging == lagging {
+            return;
+        }
+
+        self.lagging = lagging;
+
+        if lagging {
+            self.lagging_count.fetch_add(1, Ordering::SeqCst);
+        } else {
+            self.lagging_count.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        let _ = self.lagging_changed.send(());
+    }
+}
+
+impl<T> Drop for Reader<T> {
+    fn drop(&mut self) {
+        self.set_lagging(false);
+    }
+}
+
+/// A receiver of the bus which buffers at most a fixed number of messages,
+/// dropping the oldest ones when it falls behind.
+///
+/// See [subscribe_bounded][Bus::subscribe_bounded].
+pub struct BoundedReader<T> {
+    shared: Arc<Bounded<T>>,
+}
+
+impl<T> BoundedReader<T> {
+    /// Receive the next message from the bus.
+    ///
+    /// Fails with [BusError::Lagged] once if any messages were dropped since
+    /// the last receive.
+    pub async fn recv(&mut self) -> Result<T, BusError> {
+        Ok(self.recv_seq().await?.1)
+    }
+
+    /// Receive the next message from the bus together with its sequence
+    /// number.
+    pub async fn recv_seq(&mut self) -> Result<(u64, T), BusError> {
+        loop {
+            if let Some(result) = self.shared.pop() {
+                return result;
+            }
+
+            self.shared.notify.notified().await;
+        }
+    }
+}
+
+/// Messages buffered for a [BoundedReader].
+struct Bounded<T> {
+    state: Mutex<BoundedState<T>>,
+    /// Notified when the state changes.
+    notify: tokio::sync::Notify,
+}
+
+struct BoundedState<T> {
+    capacity: usize,
+    queue: VecDeque<(u64, T)>,
+    /// Number of messages dropped since the last receive.
+    dropped: u64,
+    closed: bool,
+}
+
+impl<T> Bounded<T> {
+    /// Push the result of receiving from the bus, dropping the oldest
+    /// message if full.
+    ///
+    /// Returns `false` if the bus was shut down.
+    fn push(&self, result: Result<(u64, T), BusError>) -> bool {
+        let open = {
+            let mut state = self.state.lock();
+
+            match result {
+                Ok(m) => {
+                    if state.queue.len() >= state.capacity {
+                        state.queue.pop_front();
+                        state.dropped += 1;
+                    }
+
+                    state.queue.push_back(m);
+                }
+                Err(BusError::Lagged(n)) => {
+                    state.dropped += n;
+                }
+                Err(..) => {
+                    state.closed = true;
+                }
+            }
+
+            !state.closed
+        };
+
+        self.notify.notify_one();
+        open
+    }
+
+    /// Pop the next result to return to the reader, if one is ready.
+    fn pop(&self) -> Option<Result<(u64, T), BusError>> {
+        let mut state = self.state.lock();
+
+        if state.dropped > 0 {
+            return Some(Err(BusError::Lagged(std::mem::take(&mut state.dropped))));
+        }
+
+        if let Some(m) = state.queue.pop_front() {
+            return Some(Ok(m));
+        }
+
+        if state.closed {
+            return Some(Err(BusError::Closed));
+        }
+
         None
     }
 }
 
-pub type Reader<T> = broadcast::Receiver<T>;
+impl Bus<Global> {
+    /// Broadcast the current health of the bus.
+    pub async fn emit_health(&self) {
+        self.send(Global::Health {
+            receivers: self.receiver_count(),
+            dropped: self.dropped_count(),
+            uptime_secs: self.uptime().as_secs(),
+        })
+        .await;
+    }
+
+    /// Spawn a task which broadcasts the health of the bus once every
+    /// `interval`.
+    ///
+    /// The task ends when the bus is shut down.
+    pub fn spawn_health(&self, interval: Duration) -> task::Handle<()> {
+        let bus = self.clone();
+        let mut shutdown = self.inner.shutdown_rx.clone();
+
+        task::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => (),
+                    _ = shutdown.changed() => return,
+                }
+
+                if *shutdown.borrow() {
+                    return;
+                }
+
+                bus.emit_health().await;
+            }
+        })
+    }
+}
+
+/// Merge several readers into a single stream of their messages.
+///
+/// Lagging is ignored. A reader whose bus is shut down only stops
+/// contributing to the stream, which ends once every reader has closed.
+pub fn merge<T>(readers: Vec<Reader<T>>) -> BoxStream<'static, T>
+where
+    T: 'static + Clone + Send,
+{
+    let streams = readers.into_iter().map(|mut reader| {
+        Box::pin(async_stream::stream! {
+            loop {
+                match reader.recv().await {
+                    Ok(m) => yield m,
+                    Err(BusError::Closed) => break,
+                    Err(..) => (),
+                }
+            }
+        })
+    });
+
+    Box::pin(futures_util::stream::select_all(streams))
+}
+
+/// A callback invoked with every serialized message.
+type Tap = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Sequenced messages which are retained for replay.
+struct History<T> {
+    /// The sequence number of the last message sent.
+    seq: u64,
+    /// Retained messages, in the order they were sent.
+    entries: VecDeque<(u64, T)>,
+    /// Latest instances of all messages, together with when they were sent.
+    ///
+    /// NB: uses the tokio clock, so that tests can control how old messages
+    /// are.
+    ///
+    /// Kept under the same lock as the sequence so that a snapshot of it
+    /// can be taken atomically with subscribing.
+    latest: HashMap<MessageId, (tokio::time::Instant, T)>,
+    /// Version of the latest messages, bumped whenever they change.
+    version: u64,
+    /// Keys of latest messages which are never evicted to make room for
+    /// others.
+    pinned: HashSet<MessageId>,
+}
+
+impl<T> History<T> {
+    /// Store the latest message with the given key.
+    ///
+    /// If more than `max_cached` keys are then cached, the least recently
+    /// updated keys which aren't pinned are evicted.
+    fn cache(&mut self, id: MessageId, m: T, max_cached: Option<usize>) {
+        self.latest.insert(id, (tokio::time::Instant::now(), m));
+        self.version += 1;
+
+        let max_cached = match max_cached {
+            Some(max_cached) => max_cached,
+            None => return,
+        };
+
+        while self.latest.len() > max_cached {
+            let oldest = self
+                .latest
+                .iter()
+                .filter(|(key, _)| **key != id && !self.pinned.contains(*key))
+                .min_by_key(|(_, (sent, _))| *sent)
+                .map(|(key, _)| *key);
+
+            match oldest {
+                Some(oldest) => {
+                    self.latest.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Evict latest messages which are older than `max_age`, if set.
+    fn evict(&mut self, max_age: Option<Duration>) {
+        if let Some(max_age) = max_age {
+            let now = tokio::time::Instant::now();
+            let len = self.latest.len();
+            self.latest
+                .retain(|_, (sent, _)| now.saturating_duration_since(*sent) <= max_age);
+
+            if self.latest.len() != len {
+                self.version += 1;
+            }
+        }
+    }
+}
+
+/// Channels to individual connections.
+struct Connections<T> {
+    /// The identifier of the next connection.
+    next_id: u64,
+    senders: HashMap<u64, mpsc::UnboundedSender<T>>,
+    /// Peer addresses of connections, for those where it is known.
+    peers: HashMap<u64, SocketAddr>,
+}
+
+/// A connection registered with the bus, which can be sent messages
+/// individually through [Bus::send_to].
+pub struct Connection<T>
+where
+    T: Clone,
+{
+    id: u64,
+    rx: mpsc::UnboundedReceiver<T>,
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Connection<T>
+where
+    T: Clone,
+{
+    /// The identifier of the connection.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Receive the next message sent to this connection.
+    pub async fn recv(&mut self) -> Option<T> {
+        self.rx.recv().await
+    }
+}
+
+impl<T> Drop for Connection<T>
+where
+    T: Clone,
+{
+    fn drop(&mut self) {
+        let mut connections = self.inner.connections.lock();
+        connections.senders.remove(&self.id);
+        connections.peers.remove(&self.id);
+    }
+}
 
 struct Inner<T>
 where
     T: Clone,
 {
-    subs: broadcast::Sender<T>,
-    /// Latest instances of all messages.
-    latest: RwLock<HashMap<&'static str, T>>,
+    /// Token identifying this bus instance, used to resume connections.
+    token: String,
+    subs: broadcast::Sender<(u64, T)>,
+    /// Channel used for high priority messages.
+    high: broadcast::Sender<(u64, T)>,
+    /// Recently sent messages.
+    history: Mutex<History<T>>,
+    /// The last message sent, if we are configured to retain it.
+    last_sent: Option<Mutex<Option<T>>>,
+    /// Number of readers whose last receive lagged.
+    lagging_count: Arc<AtomicUsize>,
+    /// Signalled when the number of lagging readers changes.
+    lagging_changed: Arc<watch::Sender<()>>,
+    /// Receiver cloned by senders waiting for lagging readers.
+    lagging_changed_rx: watch::Receiver<()>,
+    /// Total number of messages missed by lagging readers.
+    dropped: Arc<AtomicU64>,
+    /// When the bus was constructed.
+    started: Instant,
+    /// Registered connections.
+    connections: Mutex<Connections<T>>,
+    /// Sender used to signal that the bus has been shut down.
+    shutdown: watch::Sender<bool>,
+    /// Receiver cloned into every reader.
+    shutdown_rx: watch::Receiver<bool>,
+    /// Callback to invoke with serialized messages.
+    tap: Option<Tap>,
+    /// Window during which messages are batched before being written.
+    batch_window: Duration,
+    /// Maximum size of a serialized message.
+    max_message_size: Option<usize>,
+    /// Maximum age of latest messages.
+    max_age: Option<Duration>,
+    /// Maximum number of keys for which the latest message is cached.
+    max_cached: Option<usize>,
+    /// Pretty-print serialized messages.
+    pretty: bool,
+    /// Flag whether serialized messages are replays.
+    flag_replays: bool,
+    /// Total number of bytes serialized for clients, by message key.
+    byte_stats: Mutex<HashMap<MessageId, u64>>,
+}
+
+/// A builder for a Bus.
+#[derive(Default)]
+pub struct Builder {
+    last_sent: bool,
+    tap: Option<Tap>,
+    batch_window: Duration,
+    max_message_size: Option<usize>,
+    max_age: Option<Duration>,
+    max_cached: Option<usize>,
+    pretty: bool,
+    flag_replays: bool,
+}
+
+impl Builder {
+    /// Retain the most recently sent message, regardless of its key.
+    ///
+    /// This costs an extra clone for every message sent.
+    pub fn last_sent(self, last_sent: bool) -> Self {
+        Self { last_sent, ..self }
+    }
+
+    /// Invoke the given callback with every message exactly as it is
+    /// serialized for clients, before it is written.
+    pub fn tap<F>(self, tap: F) -> Self
+    where
+        F: 'static + Fn(&str) + Send + Sync,
+    {
+        Self {
+            tap: Some(Arc::new(tap)),
+            ..self
+        }
+    }
+
+    /// Batch messages which are ready within the given window into a single
+    /// write to each client, separated by newlines.
+    ///
+    /// Defaults to zero, which writes every message immediately.
+    pub fn batch_window(self, batch_window: Duration) -> Self {
+        Self {
+            batch_window,
+            ..self
+        }
+    }
+
+    /// Skip messages which serialize to more than the given number of bytes,
+    /// rather than writing them to clients.
+    pub fn max_message_size(self, max_message_size: usize) -> Self {
+        Self {
+            max_message_size: Some(max_message_size),
+            ..self
+        }
+    }
+
+    /// Stop including latest messages in snapshots once they are older than
+    /// the given age, so that stale state isn't replayed to new clients.
+    ///
+    /// Messages which are sent frequently are unaffected.
+    pub fn max_age(self, max_age: Duration) -> Self {
+        Self {
+            max_age: Some(max_age),
+            ..self
+        }
+    }
+
+    /// Cache the latest message for at most the given number of keys,
+    /// evicting the least recently updated key when exceeded.
+    ///
+    /// Keys can be protected from eviction with [pin_key][Bus::pin_key].
+    pub fn max_cached(self, max_cached: usize) -> Self {
+        Self {
+            max_cached: Some(max_cached),
+            ..self
+        }
+    }
+
+    /// Pretty-print messages with newlines and indentation, which is useful
+    /// when reading the raw stream while debugging.
+    ///
+    /// Since messages then span multiple lines, batched messages are
+    /// length-prefixed instead of newline-separated.
+    pub fn pretty(self, pretty: bool) -> Self {
+        Self { pretty, ..self }
+    }
+
+    /// Tag every message with a `replay` field, which is `true` for cached
+    /// messages replayed to a client when it connects and `false` for live
+    /// ones.
+    ///
+    /// This lets clients avoid re-triggering things like animations for
+    /// state they have already seen.
+    pub fn flag_replays(self, flag_replays: bool) -> Self {
+        Self {
+            flag_replays,
+            ..self
+        }
+    }
+
+    /// Construct a new bus.
+    pub fn build<T>(self) -> Bus<T>
+    where
+        T: Clone,
+    {
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let (lagging_changed, lagging_changed_rx) = watch::channel(());
+
+        Bus {
+            inner: Arc::new(Inner {
+                token: uuid::Uuid::new_v4().to_string(),
+                subs: broadcast::channel(CAPACITY).0,
+                high: broadcast::channel(HIGH_CAPACITY).0,
+                history: Mutex::new(History {
+                    seq: 0,
+                    entries: VecDeque::with_capacity(CAPACITY),
+                    latest: HashMap::new(),
+                    version: 0,
+                    pinned: HashSet::new(),
+                }),
+                last_sent: if self.last_sent {
+                    Some(Mutex::new(None))
+                } else {
+                    None
+                },
+                lagging_count: Arc::new(AtomicUsize::new(0)),
+                lagging_changed: Arc::new(lagging_changed),
+                lagging_changed_rx,
+                dropped: Arc::new(AtomicU64::new(0)),
+                started: Instant::now(),
+                connections: Mutex::new(Connections {
+                    next_id: 0,
+                    senders: HashMap::new(),
+                    peers: HashMap::new(),
+                }),
+                shutdown,
+                shutdown_rx,
+                tap: self.tap,
+                batch_window: self.batch_window,
+                max_message_size: self.max_message_size,
+                max_age: self.max_age,
+                max_cached: self.max_cached,
+                pretty: self.pretty,
+                flag_replays: self.flag_replays,
+                byte_stats: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
 }
 
 /// Bus system.
@@ -38,47 +745,916 @@ where
 {
     /// Create a new notifier.
     pub fn new() -> Self {
-        Self {
-            inner: Arc::new(Inner {
-                subs: broadcast::channel(64).0,
-                latest: RwLock::new(HashMap::new()),
+        Builder::default().build()
+    }
+
+    /// Construct a builder for a bus.
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// Send a message through the bus.
+    pub async fn send(&self, m: T)
+    where
+        T: Message,
+    {
+        if m.reliable() {
+            if let Err(e) = self.wait_for_lagging(RELIABLE_TIMEOUT).await {
+                log::warn!("sending reliable message anyway: {}", e);
+            }
+        }
+
+        if let Some(last_sent) = &self.inner.last_sent {
+            *last_sent.lock() = Some(m.clone());
+        }
+
+        let priority = m.priority();
+        self.broadcast(priority, m.id(), m);
+    }
+
+    /// Send a message once no reader is lagging behind, waiting at most
+    /// `timeout` for lagging readers to catch up.
+    ///
+    /// The bus never blocks senders, so instead readers which lag behind are
+    /// considered to be the bus being under pressure. This gives producers a
+    /// middle ground between flooding such readers and not sending at all.
+    /// Returns [BusError::Full] if readers are still lagging once the timeout
+    /// has elapsed, in which case the message is not sent.
+    pub async fn send_async(&self, m: T, timeout: Duration) -> Result<(), BusError>
+    where
+        T: Message,
+    {
+        self.wait_for_lagging(timeout).await?;
+        self.send(m).await;
+        Ok(())
+    }
+
+    /// Wait at most `timeout` for there to be no lagging readers.
+    async fn wait_for_lagging(&self, timeout: Duration) -> Result<(), BusError> {
+        if self.lagging_count() == 0 {
+            return Ok(());
+        }
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        // NB: cloned before checking, so that no change is missed.
+        let mut changed = self.inner.lagging_changed_rx.clone();
+
+        while self.lagging_count() > 0 {
+            match tokio::time::timeout_at(deadline, changed.changed()).await {
+                Ok(Ok(())) => (),
+                Ok(Err(..)) => return Err(BusError::Closed),
+                Err(..) => return Err(BusError::Full),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Send a message only if no message with the same key is cached.
+    ///
+    /// The check and the send happen in the same critical section, so this
+    /// never overwrites a message which was sent concurrently. Returns
+    /// whether the message was sent. Messages without a key are always sent.
+    pub async fn send_if_absent(&self, m: T) -> bool
+    where
+        T: Message,
+    {
+        let id = m.id();
+        let mut history = self.inner.history.lock();
+        history.evict(self.inner.max_age);
+
+        if let Some(id) = id {
+            if history.latest.contains_key(&id) {
+                return false;
+            }
+        }
+
+        if let Some(last_sent) = &self.inner.last_sent {
+            *last_sent.lock() = Some(m.clone());
+        }
+
+        let priority = m.priority();
+        self.broadcast_locked(&mut history, priority, id, m);
+        true
+    }
+
+    /// Send several messages in order, as if by [send][Bus::send], in a single
+    /// critical section.
+    ///
+    /// A client which connects will either see all of the messages or none
+    /// of them.
+    pub async fn send_all(&self, ms: Vec<T>)
+    where
+        T: Message,
+    {
+        if let (Some(last_sent), Some(m)) = (&self.inner.last_sent, ms.last()) {
+            *last_sent.lock() = Some(m.clone());
+        }
+
+        let mut history = self.inner.history.lock();
+
+        for m in ms {
+            let priority = m.priority();
+            self.broadcast_locked(&mut history, priority, m.id(), m);
+        }
+    }
+
+    /// Send `delta` through the bus while caching `full` in place of it.
+    ///
+    /// This allows sending a small message describing a change to connected
+    /// clients, while clients which connect later still receive the complete
+    /// state from the cache.
+    pub async fn send_delta(&self, delta: T, full: T)
+    where
+        T: Message,
+    {
+        if let Some(last_sent) = &self.inner.last_sent {
+            *last_sent.lock() = Some(delta.clone());
+        }
+
+        let mut history = self.inner.history.lock();
+
+        if let Some(id) = full.id() {
+            history.cache(id, full, self.inner.max_cached);
+        }
+
+        let priority = delta.priority();
+        let id = delta.id();
+        self.broadcast_locked(&mut history, priority, id, delta);
+    }
+
+    /// Send a synced and cloneable message.
+    pub fn send_sync(&self, m: T)
+    where
+        T: 'static + Clone + Send + Sync,
+    {
+        self.broadcast(Priority::Normal, None, m);
+    }
+
+    /// Assign a sequence number to the message, store it as the latest
+    /// message with the given `id`, and broadcast it.
+    ///
+    /// The history lock is held while broadcasting so that readers observe
+    /// messages in sequence order.
+    fn broadcast(&self, priority: Priority, id: Option<MessageId>, m: T) {
+        let mut history = self.inner.history.lock();
+        self.broadcast_locked(&mut history, priority, id, m);
+    }
+
+    /// Same as [broadcast][Bus::broadcast], but with the history lock already
+    /// held.
+    fn broadcast_locked(
+        &self,
+        history: &mut History<T>,
+        priority: Priority,
+        id: Option<MessageId>,
+        m: T,
+    ) {
+        if let Some(id) = id {
+            history.cache(id, m.clone(), self.inner.max_cached);
+        }
+
+        history.seq += 1;
+        let seq = history.seq;
+
+        if history.entries.len() == CAPACITY {
+            history.entries.pop_front();
+        }
+
+        history.entries.push_back((seq, m.clone()));
+
+        let _ = match priority {
+            Priority::Normal => self.inner.subs.send((seq, m)),
+            Priority::High => self.inner.high.send((seq, m)),
+        };
+    }
+
+    /// Spawn a task which re-broadcasts every latest message once every
+    /// `interval`, so that clients which fell out of sync are corrected.
+    ///
+    /// Does nothing on ticks where there are no latest messages. The task
+    /// ends when the bus is shut down.
+    pub fn spawn_resync(&self, interval: Duration) -> task::Handle<()>
+    where
+        T: Message,
+    {
+        let bus = self.clone();
+        let mut shutdown = self.inner.shutdown_rx.clone();
+
+        task::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            // The first tick completes immediately.
+            interval.tick().await;
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => (),
+                    _ = shutdown.changed() => return,
+                }
+
+                if *shutdown.borrow() {
+                    return;
+                }
+
+                // Not re-stored as latest, so that resyncing doesn't keep
+                // stale messages from aging out.
+                for m in bus.latest().await {
+                    let priority = m.priority();
+                    bus.broadcast(priority, None, m);
+                }
+            }
+        })
+    }
+
+    /// Get the latest messages received.
+    pub async fn latest(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        let mut history = self.inner.history.lock();
+        history.evict(self.inner.max_age);
+        history.latest.values().map(|(_, m)| m.clone()).collect()
+    }
+
+    /// Get a tag which changes whenever the latest messages change, suitable
+    /// for use as an HTTP ETag.
+    pub fn latest_etag(&self) -> u64 {
+        let mut history = self.inner.history.lock();
+        history.evict(self.inner.max_age);
+        history.version
+    }
+
+    /// Protect the latest message with the given key from being evicted when
+    /// the [maximum number of cached keys][Builder::max_cached] is exceeded.
+    ///
+    /// Pinned messages still age out if a maximum age is configured.
+    pub fn pin_key(&self, id: MessageId) {
+        self.inner.history.lock().pinned.insert(id);
+    }
+
+    /// Get the keys of the latest messages which are currently cached,
+    /// without cloning the messages.
+    pub fn cached_keys(&self) -> Vec<&'static str> {
+        let mut history = self.inner.history.lock();
+        history.evict(self.inner.max_age);
+        history.latest.keys().map(|id| id.as_str()).collect()
+    }
+
+    /// Get the latest message received with the given key.
+    pub async fn latest_by_key(&self, key: &str) -> Option<T> {
+        let mut history = self.inner.history.lock();
+        history.evict(self.inner.max_age);
+
+        history
+            .latest
+            .iter()
+            .find(|(id, _)| id.as_str() == key)
+            .map(|(_, (_, m))| m.clone())
+    }
+
+    /// Get the total number of bytes serialized for clients so far, by
+    /// message key.
+    ///
+    /// Messages are counted once for every client they are serialized for.
+    /// Messages without a key are not counted.
+    pub fn byte_stats(&self) -> HashMap<&'static str, u64> {
+        self.inner
+            .byte_stats
+            .lock()
+            .iter()
+            .map(|(id, bytes)| (id.as_str(), *bytes))
+            .collect()
+    }
+
+    /// Get the most recently sent message, regardless of its key.
+    ///
+    /// Always returns `None` unless the bus was built with
+    /// [Builder::last_sent] enabled.
+    pub fn last_sent(&self) -> Option<T> {
+        self.inner.last_sent.as_ref()?.lock().clone()
+    }
+
+    /// Serialize a message the way it is sent to clients, optionally tagged
+    /// with its sequence number.
+    ///
+    /// Returns `None` if the message exceeds the configured
+    /// [maximum size][Builder::max_message_size] and should be skipped.
+    pub fn serialize(&self, seq: Option<u64>, m: &T) -> Result<Option<String>, BusError>
+    where
+        T: Message,
+    {
+        self.serialize_with(seq, false, m)
+    }
+
+    /// Serialize a cached message which is replayed to a client, like the
+    /// latest messages sent when it connects.
+    ///
+    /// See [Builder::flag_replays].
+    pub fn serialize_replay(&self, m: &T) -> Result<Option<String>, BusError>
+    where
+        T: Message,
+    {
+        self.serialize_with(None, true, m)
+    }
+
+    fn serialize_with(
+        &self,
+        seq: Option<u64>,
+        replay: bool,
+        m: &T,
+    ) -> Result<Option<String>, BusError>
+    where
+        T: Message,
+    {
+        let replay = if self.inner.flag_replays {
+            Some(replay)
+        } else {
+            None
+        };
+
+        let json = to_json(seq, replay, m, self.inner.pretty)?;
+        self.checked(m.id(), json)
+    }
+
+    /// Serialize a message for an event stream, which is always compact since
+    /// every event is sent as a single line.
+    ///
+    /// Returns the `type` of the message if it has one, together with the
+    /// serialized message. Returns `None` like [serialize][Bus::serialize]
+    /// does.
+    pub(crate) fn serialize_event(
+        &self,
+        replay: bool,
+        m: &T,
+    ) -> Result<Option<(Option<String>, String)>, BusError>
+    where
+        T: Message,
+    {
+        let replay = if self.inner.flag_replays {
+            Some(replay)
+        } else {
+            None
+        };
+
+        let value = to_value(None, replay, m)?;
+        let ty = value
+            .get("type")
+            .and_then(|ty| ty.as_str())
+            .map(String::from);
+        let json = serde_json::to_string(&value)?;
+        Ok(self.checked(m.id(), json)?.map(|json| (ty, json)))
+    }
+
+    /// Serialize a message like [serialize][Bus::serialize], but only with
+    /// the fields which changed since the last message with the same key
+    /// that was passed through `deltas`.
+    ///
+    /// Deltas are flagged with `"delta": true`. Messages without a key or
+    /// without a previous value are serialized in full. Returns `None` if
+    /// nothing changed.
+    pub fn serialize_delta(
+        &self,
+        deltas: &mut Deltas,
+        seq: Option<u64>,
+        m: &T,
+    ) -> Result<Option<String>, BusError>
+    where
+        T: Message,
+    {
+        let id = match m.id() {
+            Some(id) => id,
+            None => return self.serialize(seq, m),
+        };
+
+        let delta = match deltas.diff(id, serde_json::to_value(m)?) {
+            Some(delta) => delta,
+            None => return Ok(None),
+        };
+
+        let replay = if self.inner.flag_replays {
+            Some(false)
+        } else {
+            None
+        };
+
+        let json = to_json(seq, replay, &delta, self.inner.pretty)?;
+        self.checked(Some(id), json)
+    }
+
+    /// Check the size of a serialized message and account for it.
+    fn checked(&self, id: Option<MessageId>, json: String) -> Result<Option<String>, BusError> {
+        if let Some(max) = self.inner.max_message_size {
+            if json.len() > max {
+                log::warn!(
+                    "skipping bus message of {} bytes, exceeding the maximum of {} bytes",
+                    json.len(),
+                    max
+                );
+                return Ok(None);
+            }
+        }
+
+        if let Some(id) = id {
+            *self.inner.byte_stats.lock().entry(id).or_default() += json.len() as u64;
+        }
+
+        if let Some(tap) = &self.inner.tap {
+            tap(&json);
+        }
+
+        Ok(Some(json))
+    }
+
+    /// Serialize a message to the payload a client would see, without
+    /// sending it.
+    pub fn preview(&self, m: &T) -> Result<String, BusError>
+    where
+        T: Message,
+    {
+        Ok(to_json(None, None, m, self.inner.pretty)?)
+    }
+
+    /// Whether messages are pretty-printed.
+    pub fn pretty(&self) -> bool {
+        self.inner.pretty
+    }
+
+    /// The window during which messages are batched before being written to
+    /// clients.
+    pub fn batch_window(&self) -> Duration {
+        self.inner.batch_window
+    }
+
+    /// The token identifying this bus instance.
+    ///
+    /// Clients hand this back when resuming, so that a resume against a
+    /// restarted bus can be detected.
+    pub fn token(&self) -> &str {
+        &self.inner.token
+    }
+
+    /// Get the messages sent after `last_seq` up to and including `until`.
+    ///
+    /// Returns `None` if some of those messages are no longer retained.
+    pub fn replay(&self, last_seq: u64, until: u64) -> Option<Vec<(u64, T)>> {
+        if last_seq >= until {
+            return Some(Vec::new());
+        }
+
+        let history = self.inner.history.lock();
+
+        match history.entries.front() {
+            Some((first, _)) if *first <= last_seq + 1 => (),
+            _ => return None,
+        }
+
+        Some(
+            history
+                .entries
+                .iter()
+                .filter(|(seq, _)| *seq > last_seq && *seq <= until)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Shut down the bus.
+    ///
+    /// Every reader will see the bus as closed, allowing connected clients to
+    /// be told that the shutdown was intentional.
+    pub fn shutdown(&self) {
+        let _ = self.inner.shutdown.send(true);
+    }
+
+    /// Register a connection which can be sent messages individually.
+    pub fn connect(&self) -> Connection<T> {
+        self.connect_from(None)
+    }
+
+    /// Register a connection from the given peer, which is listed by
+    /// [connected_peers][Bus::connected_peers] for as long as the connection
+    /// is alive.
+    pub fn connect_from(&self, peer: Option<SocketAddr>) -> Connection<T> {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        let mut connections = self.inner.connections.lock();
+        let id = connections.next_id;
+        connections.next_id += 1;
+        connections.senders.insert(id, tx);
+
+        if let Some(peer) = peer {
+            connections.peers.insert(id, peer);
+        }
+
+        Connection {
+            id,
+            rx,
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Number of registered connections.
+    ///
+    /// A connection is unregistered as soon as it's dropped, regardless of
+    /// how the client it belongs to terminated.
+    pub fn connection_count(&self) -> usize {
+        self.inner.connections.lock().senders.len()
+    }
+
+    /// Peer addresses of all registered connections, in the order they
+    /// connected.
+    ///
+    /// Connections whose peer address is unknown are not included.
+    pub fn connected_peers(&self) -> Vec<SocketAddr> {
+        let connections = self.inner.connections.lock();
+        let mut peers = connections.peers.iter().collect::<Vec<_>>();
+        peers.sort_by_key(|(id, _)| **id);
+        peers.into_iter().map(|(_, peer)| *peer).collect()
+    }
+
+    /// Send a message to a single connection, bypassing the shared bus.
+    ///
+    /// Returns `false` if no such connection exists.
+    pub fn send_to(&self, id: u64, m: T) -> bool {
+        let connections = self.inner.connections.lock();
+
+        match connections.senders.get(&id) {
+            Some(tx) => tx.send(m).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Number of readers of the bus.
+    pub fn receiver_count(&self) -> usize {
+        self.inner.subs.receiver_count()
+    }
+
+    /// How long a client should wait before reconnecting after the bus was
+    /// shut down.
+    ///
+    /// This grows with the number of readers so that reconnects after a
+    /// restart are spread out under load, and includes a random jitter of
+    /// up to half as much so that clients don't reconnect in lockstep.
+    pub fn retry_after(&self) -> Duration {
+        use rand::Rng as _;
+
+        let readers = usize::min(self.receiver_count(), u32::MAX as usize) as u32;
+        let base = Duration::min(
+            RETRY_AFTER_BASE + RETRY_AFTER_PER_READER * readers,
+            RETRY_AFTER_MAX,
+        );
+
+        let jitter = rand::thread_rng().gen_range(0..=base.as_millis() as u64 / 2);
+        base + Duration::from_millis(jitter)
+    }
+
+    /// Number of readers which are currently lagging behind, in that their
+    /// last receive skipped messages.
+    pub fn lagging_count(&self) -> usize {
+        self.inner.lagging_count.load(Ordering::SeqCst)
+    }
+
+    /// Total number of messages which readers missed because they lagged
+    /// behind.
+    pub fn dropped_count(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+
+    /// How long ago the bus was constructed.
+    pub fn uptime(&self) -> Duration {
+        self.inner.started.elapsed()
+    }
+
+    /// Format the health counters of the bus in the Prometheus text
+    /// exposition format.
+    pub fn metrics_text(&self) -> String {
+        use std::fmt::Write as _;
+
+        let (sent, cached) = {
+            let history = self.inner.history.lock();
+            (history.seq, history.latest.len() as u64)
+        };
+
+        // Counters are u64, so gauges are widened to match rather than
+        // truncating the counters.
+        let metrics = [
+            ("setmod_bus_sent_total", "counter", sent),
+            (
+                "setmod_bus_receivers",
+                "gauge",
+                self.receiver_count() as u64,
+            ),
+            ("setmod_bus_lagging", "gauge", self.lagging_count() as u64),
+            ("setmod_bus_dropped_total", "counter", self.dropped_count()),
+            ("setmod_bus_cached", "gauge", cached),
+        ];
+
+        let mut out = String::new();
+
+        for (name, ty, value) in &metrics {
+            let _ = writeln!(out, "# TYPE {} {}", name, ty);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        out
+    }
+
+    /// Create a receiver of the bus.
+    pub fn subscribe(&self) -> Reader<T> {
+        self.subscribe_seq().1
+    }
+
+    /// Create a receiver of the bus which buffers at most `capacity`
+    /// messages, dropping the oldest ones instead of lagging behind.
+    ///
+    /// This suits consumers which only care about the latest state. Messages
+    /// are drained from the bus by a background task, so a slow consumer
+    /// never causes the bus to drop messages. The task ends when the bus is
+    /// shut down or the next message after the reader is dropped.
+    pub fn subscribe_bounded(&self, capacity: usize) -> BoundedReader<T>
+    where
+        T: 'static + Send + Clone,
+    {
+        let mut reader = self.subscribe();
+
+        let shared = Arc::new(Bounded {
+            state: Mutex::new(BoundedState {
+                capacity: usize::max(capacity, 1),
+                queue: VecDeque::new(),
+                dropped: 0,
+                closed: false,
             }),
+            notify: tokio::sync::Notify::new(),
+        });
+
+        let weak = Arc::downgrade(&shared);
+
+        task::spawn(async move {
+            loop {
+                let result = reader.recv_seq().await;
+
+                let shared = match weak.upgrade() {
+                    Some(shared) => shared,
+                    None => return,
+                };
+
+                if !shared.push(result) {
+                    return;
+                }
+            }
+        });
+
+        BoundedReader { shared }
+    }
+
+    /// Subscribe to the bus, only yielding the messages which `f` maps to
+    /// `Some`.
+    ///
+    /// Lagging is ignored, and the stream ends when the bus is shut down.
+    pub fn subscribe_filter_map<U, F>(&self, f: F) -> BoxStream<'static, U>
+    where
+        T: 'static + Send,
+        U: 'static + Send,
+        F: 'static + Send + Fn(T) -> Option<U>,
+    {
+        let mut reader = self.subscribe();
+
+        Box::pin(async_stream::stream! {
+            loop {
+                match reader.recv().await {
+                    Ok(m) => {
+                        if let Some(m) = f(m) {
+                            yield m;
+                        }
+                    }
+                    Err(BusError::Closed) => break,
+                    Err(..) => (),
+                }
+            }
+        })
+    }
+
+    /// Create a receiver of the bus, together with the sequence number of
+    /// the last message sent before it was subscribed.
+    pub fn subscribe_seq(&self) -> (u64, Reader<T>) {
+        let history = self.inner.history.lock();
+        (history.seq, self.reader(history.seq))
+    }
+
+    /// Create a receiver of the bus, together with a snapshot of the latest
+    /// messages.
+    ///
+    /// Both are taken in the same critical section, so every message is
+    /// either part of the snapshot or received through the reader. Never
+    /// both, and never neither.
+    pub fn subscribe_with_snapshot(&self) -> (Vec<T>, Reader<T>) {
+        let (_, snapshot, reader) = self.subscribe_with_snapshot_seq();
+        (snapshot, reader)
+    }
+
+    /// Same as [subscribe_with_snapshot][Bus::subscribe_with_snapshot], but
+    /// also returns the sequence number of the last message sent before it
+    /// was subscribed.
+    pub fn subscribe_with_snapshot_seq(&self) -> (u64, Vec<T>, Reader<T>) {
+        let mut history = self.inner.history.lock();
+        history.evict(self.inner.max_age);
+        let snapshot = history.latest.values().map(|(_, m)| m.clone()).collect();
+        (history.seq, snapshot, self.reader(history.seq))
+    }
+
+    /// Construct a new reader.
+    ///
+    /// Must be called while holding the history lock, where `seq` is the
+    /// sequence number of the last message sent.
+    fn reader(&self, seq: u64) -> Reader<T> {
+        Reader {
+            normal: self.inner.subs.subscribe(),
+            high: self.inner.high.subscribe(),
+            resume_seq: seq,
+            ahead: BTreeSet::new(),
+            shutdown: self.inner.shutdown_rx.clone(),
+            lagging: false,
+            lagging_count: self.inner.lagging_count.clone(),
+            lagging_changed: self.inner.lagging_changed.clone(),
+            dropped: self.inner.dropped.clone(),
+        }
+    }
+}
+
+/// Serialize a message, optionally tagged with its sequence number and
+/// whether it is a replay.
+fn to_json<T>(
+    seq: Option<u64>,
+    replay: Option<bool>,
+    m: &T,
+    pretty: bool,
+) -> Result<String, serde_json::Error>
+where
+    T: serde::Serialize,
+{
+    if seq.is_none() && replay.is_none() {
+        if pretty {
+            return serde_json::to_string_pretty(m);
+        }
+
+        return serde_json::to_string(m);
+    }
+
+    let value = to_value(seq, replay, m)?;
+
+    if pretty {
+        return serde_json::to_string_pretty(&value);
+    }
+
+    serde_json::to_string(&value)
+}
+
+/// Convert a message into a JSON value, optionally tagged like
+/// [to_json].
+fn to_value<T>(
+    seq: Option<u64>,
+    replay: Option<bool>,
+    m: &T,
+) -> Result<serde_json::Value, serde_json::Error>
+where
+    T: serde::Serialize,
+{
+    let mut value = serde_json::to_value(m)?;
+
+    if let serde_json::Value::Object(map) = &mut value {
+        if let Some(seq) = seq {
+            map.insert(String::from("seq"), serde_json::Value::from(seq));
+        }
+
+        if let Some(replay) = replay {
+            map.insert(String::from("replay"), serde_json::Value::from(replay));
+        }
+    }
+
+    Ok(value)
+}
+
+/// Frame a batch of serialized messages into a single write.
+///
+/// Messages are separated by newlines, unless they are `length_prefixed` in
+/// which case every message is preceded by its length in bytes and a
+/// newline. See [BusDecoder] for the inverse.
+pub(crate) fn frame(batch: &[String], length_prefixed: bool) -> String {
+    if !length_prefixed {
+        return batch.join("\n");
+    }
+
+    batch
+        .iter()
+        .map(|m| format!("{}\n{}", m.len(), m))
+        .collect()
+}
+
+/// The previous value of every keyed message sent to a client, used to only
+/// send what changed through [serialize_delta][Bus::serialize_delta].
+#[derive(Debug, Default)]
+pub struct Deltas {
+    previous: HashMap<MessageId, serde_json::Value>,
+}
+
+impl Deltas {
+    /// Record a message which was sent to the client in full, like when it's
+    /// replayed, so that the next delta is relative to it.
+    pub fn record<T>(&mut self, m: &T) -> Result<(), BusError>
+    where
+        T: Message,
+    {
+        if let Some(id) = m.id() {
+            self.previous.insert(id, serde_json::to_value(m)?);
+        }
+
+        Ok(())
+    }
+
+    /// Diff the given value against the previous one with the same key.
+    ///
+    /// Fields which were removed are set to `null`. The type tag is always
+    /// kept so that clients know what the delta applies to.
+    fn diff(&mut self, id: MessageId, value: serde_json::Value) -> Option<serde_json::Value> {
+        use serde_json::Value;
+
+        let previous = self.previous.insert(id, value.clone());
+
+        let (previous, current) = match (previous, value) {
+            (Some(Value::Object(previous)), Value::Object(current)) => (previous, current),
+            (_, value) => return Some(value),
+        };
+
+        let mut delta = serde_json::Map::new();
+
+        for (k, v) in &current {
+            if previous.get(k) != Some(v) {
+                delta.insert(k.clone(), v.clone());
+            }
         }
-    }
 
-    /// Send a message through the bus.
-    pub async fn send(&self, m: T)
-    where
-        T: Message,
-    {
-        if let Some(key) = m.id() {
-            let mut latest = self.inner.latest.write().await;
-            latest.insert(key, m.clone());
+        for k in previous.keys() {
+            if !current.contains_key(k) {
+                delta.insert(k.clone(), Value::Null);
+            }
+        }
+
+        if delta.is_empty() {
+            return None;
         }
 
-        let _ = self.inner.subs.send(m);
+        if let Some(ty) = current.get("type") {
+            delta.insert(String::from("type"), ty.clone());
+        }
+
+        delta.insert(String::from("delta"), Value::Bool(true));
+        Some(Value::Object(delta))
     }
+}
 
-    /// Send a synced and cloneable message.
-    pub fn send_sync(&self, m: T)
-    where
-        T: 'static + Clone + Send + Sync,
-    {
-        let _ = self.inner.subs.send(m);
+/// A decoder for frames written to bus clients, for clients written in Rust.
+///
+/// Decodes exactly what is written by the bus, including batches.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BusDecoder {
+    length_prefixed: bool,
+}
+
+impl BusDecoder {
+    /// Construct a new decoder for newline-separated messages.
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    /// Get the latest messages received.
-    pub async fn latest(&self) -> Vec<T>
-    where
-        T: Clone,
-    {
-        let latest = self.inner.latest.read().await;
-        latest.values().cloned().collect()
+    /// Decode messages which are length-prefixed, as written by a bus with
+    /// [pretty-printing][Builder::pretty] enabled.
+    pub fn length_prefixed(self, length_prefixed: bool) -> Self {
+        Self { length_prefixed }
     }
 
-    /// Create a receiver of the bus.
-    pub fn subscribe(&self) -> Reader<T> {
-        self.inner.subs.subscribe()
+    /// Decode every message in the given frame.
+    pub fn decode(&self, frame: &str) -> Result<Vec<serde_json::Value>, BusError> {
+        let mut out = Vec::new();
+
+        if !self.length_prefixed {
+            for line in frame.split('\n').filter(|line| !line.trim().is_empty()) {
+                out.push(serde_json::from_str(line).map_err(BusError::Deserialize)?);
+            }
+
+            return Ok(out);
+        }
+
+        let mut rest = frame;
+
+        while !rest.is_empty() {
+            let n = rest.find('\n').ok_or(BusError::Frame)?;
+            let len = rest[..n].parse::<usize>().map_err(|_| BusError::Frame)?;
+            let m = rest.get(n + 1..n + 1 + len).ok_or(BusError::Frame)?;
+            out.push(serde_json::from_str(m).map_err(BusError::Deserialize)?);
+            rest = &rest[n + 1 + len..];
+        }
+
+        Ok(out)
     }
 }
 
@@ -94,6 +1670,10 @@ where
 #[derive(Debug, Clone, serde::Serialize)]
 #[serde(tag = "type")]
 pub enum YouTubeEvent {
+    /// Load a video without playing it, so that it's buffered by the time it
+    /// should be played.
+    #[serde(rename = "load")]
+    Load { video_id: String },
     /// Play a new song.
     #[serde(rename = "play")]
     Play {
@@ -119,14 +1699,25 @@ pub enum YouTube {
     YouTubeVolume { volume: u32 },
 }
 
+impl YouTube {
+    /// Construct a message to load the given video without playing it.
+    pub fn load(video_id: &str) -> Self {
+        YouTube::YouTubeCurrent {
+            event: YouTubeEvent::Load {
+                video_id: video_id.to_string(),
+            },
+        }
+    }
+}
+
 impl Message for YouTube {
     /// Whether a message should be cached or not and under what key.
-    fn id(&self) -> Option<&'static str> {
+    fn id(&self) -> Option<MessageId> {
         use self::YouTube::*;
 
         match *self {
-            YouTubeCurrent { .. } => Some("youtube/current"),
-            YouTubeVolume { .. } => Some("youtube/volume"),
+            YouTubeCurrent { .. } => Some(MessageId::YouTubeCurrent),
+            YouTubeVolume { .. } => Some(MessageId::YouTubeVolume),
         }
     }
 }
@@ -137,6 +1728,14 @@ impl Message for YouTube {
 pub enum Global {
     #[serde(rename = "firework")]
     Firework,
+    /// A named visual effect, which overlays dismiss after the given
+    /// duration.
+    #[serde(rename = "effect")]
+    Effect {
+        name: String,
+        duration_ms: u64,
+        params: serde_json::Value,
+    },
     #[serde(rename = "ping")]
     Ping,
     /// Progress of current song.
@@ -145,6 +1744,9 @@ pub enum Global {
         track_id: Option<TrackId>,
         elapsed: u64,
         duration: u64,
+        /// Whether the duration is known, since some tracks report a zero
+        /// duration.
+        duration_known: bool,
     },
     #[serde(rename = "song/current")]
     SongCurrent {
@@ -154,25 +1756,199 @@ pub enum Global {
         is_playing: bool,
         elapsed: u64,
         duration: u64,
+        /// Whether the duration is known, since some tracks report a zero
+        /// duration.
+        duration_known: bool,
+        /// URL to the album art of the track, if available.
+        art_url: Option<String>,
     },
+    /// Playback of the current song was paused or resumed, without the
+    /// track changing.
+    #[serde(rename = "song/state")]
+    SongStateChanged { is_playing: bool, elapsed: u64 },
     #[serde(rename = "song/modified")]
     SongModified,
+    /// Volume of a player has changed.
+    #[serde(rename = "volume")]
+    Volume { player: String, volume: u32 },
+    /// The active playback device has changed.
+    #[serde(rename = "device")]
+    DeviceChanged { device: String, volume: Option<u32> },
+    /// A custom command was executed.
+    #[serde(rename = "command/executed")]
+    CommandExecuted {
+        channel: String,
+        command: String,
+        user: String,
+    },
+    /// A song request was accepted or rejected.
+    #[serde(rename = "song/request")]
+    SongRequest {
+        user: String,
+        track_id: Option<TrackId>,
+        accepted: bool,
+        reason: Option<String>,
+    },
+    /// Chat was cleared by a moderator.
+    #[serde(rename = "chat/clear")]
+    ChatClear { channel: String },
+    /// A user was timed out for the given number of seconds.
+    #[serde(rename = "chat/timeout")]
+    UserTimeout {
+        channel: String,
+        user: String,
+        duration: u64,
+    },
+    /// A user was banned.
+    #[serde(rename = "chat/ban")]
+    UserBan { channel: String, user: String },
+    /// Health of the bus itself.
+    #[serde(rename = "health")]
+    Health {
+        receivers: usize,
+        dropped: u64,
+        uptime_secs: u64,
+    },
+    /// The player failed to play a track.
+    #[serde(rename = "song/playback-error")]
+    PlaybackError {
+        track_id: Option<TrackId>,
+        message: String,
+    },
 }
 
 impl Message for Global {
     /// Whether a message should be cached or not and under what key.
-    fn id(&self) -> Option<&'static str> {
+    fn id(&self) -> Option<MessageId> {
         use self::Global::*;
 
         match *self {
-            SongProgress { .. } => Some("song/progress"),
-            SongCurrent { .. } => Some("song/current"),
+            SongProgress { .. } => Some(MessageId::SongProgress),
+            SongCurrent { .. } => Some(MessageId::SongCurrent),
+            Volume { .. } => Some(MessageId::Volume),
+            DeviceChanged { .. } => Some(MessageId::Device),
+            Health { .. } => Some(MessageId::Health),
             _ => None,
         }
     }
+
+    /// Song changes, fireworks and effects should get through even when the
+    /// bus is flooded with progress updates.
+    fn priority(&self) -> Priority {
+        use self::Global::*;
+
+        match *self {
+            SongCurrent { .. } | Firework | Effect { .. } => Priority::High,
+            _ => Priority::Normal,
+        }
+    }
 }
 
 impl Global {
+    /// Copy the message with the song progress in it converted from seconds
+    /// into milliseconds, for overlays which expect that.
+    pub fn progress_in_millis(&self) -> Self {
+        let mut m = self.clone();
+
+        match &mut m {
+            Global::SongProgress {
+                elapsed, duration, ..
+            }
+            | Global::SongCurrent {
+                elapsed, duration, ..
+            } => {
+                *elapsed = elapsed.saturating_mul(1000);
+                *duration = duration.saturating_mul(1000);
+            }
+            Global::SongStateChanged { elapsed, .. } => {
+                *elapsed = elapsed.saturating_mul(1000);
+            }
+            _ => (),
+        }
+
+        m
+    }
+
+    /// Construct a message triggering the named effect for the given
+    /// duration.
+    pub fn effect(name: &str, duration: Duration, params: serde_json::Value) -> Self {
+        Global::Effect {
+            name: name.to_string(),
+            duration_ms: duration.as_millis() as u64,
+            params,
+        }
+    }
+
+    /// Construct a message that the volume of the given player has changed.
+    pub fn volume(player: &str, volume: u32) -> Self {
+        Global::Volume {
+            player: player.to_string(),
+            volume,
+        }
+    }
+
+    /// Construct a message that the active playback device has changed.
+    pub fn device_changed(device: &str, volume: Option<u32>) -> Self {
+        Global::DeviceChanged {
+            device: device.to_string(),
+            volume,
+        }
+    }
+
+    /// Construct a message that a custom command was executed by the given
+    /// user.
+    pub fn command_executed(channel: &str, command: &str, user: &str) -> Self {
+        Global::CommandExecuted {
+            channel: channel.to_string(),
+            command: command.to_string(),
+            user: user.to_string(),
+        }
+    }
+
+    /// Construct a message about a song request by the given user, which was
+    /// rejected if there's a reason.
+    pub fn song_request(user: &str, track_id: Option<TrackId>, reason: Option<String>) -> Self {
+        Global::SongRequest {
+            user: user.to_string(),
+            track_id,
+            accepted: reason.is_none(),
+            reason,
+        }
+    }
+
+    /// Construct a message that chat was cleared in the given channel.
+    pub fn chat_clear(channel: &str) -> Self {
+        Global::ChatClear {
+            channel: channel.to_string(),
+        }
+    }
+
+    /// Construct a message that a user was timed out for the given number
+    /// of seconds.
+    pub fn user_timeout(channel: &str, user: &str, duration: u64) -> Self {
+        Global::UserTimeout {
+            channel: channel.to_string(),
+            user: user.to_string(),
+            duration,
+        }
+    }
+
+    /// Construct a message that a user was banned.
+    pub fn user_ban(channel: &str, user: &str) -> Self {
+        Global::UserBan {
+            channel: channel.to_string(),
+            user: user.to_string(),
+        }
+    }
+
+    /// Construct a message about a track which failed to play.
+    pub fn playback_error(track_id: Option<TrackId>, message: impl fmt::Display) -> Self {
+        Global::PlaybackError {
+            track_id,
+            message: message.to_string(),
+        }
+    }
+
     /// Construct a message about song progress.
     pub fn song_progress(song: Option<&player::Song>) -> Self {
         let song = match song {
@@ -182,18 +1958,37 @@ impl Global {
                     track_id: None,
                     elapsed: 0,
                     duration: 0,
+                    duration_known: false,
                 };
             }
         };
 
+        let (elapsed, duration) = progress(song.elapsed(), song.duration());
+
         Global::SongProgress {
             track_id: Some(song.item.track_id.clone()),
-            elapsed: song.elapsed().as_secs(),
-            duration: song.duration().as_secs(),
+            elapsed,
+            duration,
+            duration_known: duration > 0,
+        }
+    }
+
+    /// Construct a message that playback of the given song was paused or
+    /// resumed.
+    pub fn song_state_changed(song: &player::Song) -> Self {
+        let (elapsed, _) = progress(song.elapsed(), song.duration());
+
+        Global::SongStateChanged {
+            is_playing: song.state() == player::State::Playing,
+            elapsed,
         }
     }
 
     /// Construct a message that the given song is running.
+    ///
+    /// Elapsed is clamped to the duration like in
+    /// [song_progress][Global::song_progress], and a song with a zero
+    /// duration is flagged as having an unknown duration.
     pub fn song(song: Option<&player::Song>) -> Result<Self, anyhow::Error> {
         let song = match song {
             Some(song) => song,
@@ -205,21 +2000,70 @@ impl Global {
                     is_playing: false,
                     elapsed: 0,
                     duration: 0,
+                    duration_known: false,
+                    art_url: None,
                 });
             }
         };
 
+        let (elapsed, duration) = progress(song.elapsed(), song.duration());
+
         Ok(Global::SongCurrent {
             track_id: Some(song.item.track_id.clone()),
             track: Some(song.item.track.clone()),
             user: song.item.user.clone(),
             is_playing: song.state() == player::State::Playing,
-            elapsed: song.elapsed().as_secs(),
-            duration: song.duration().as_secs(),
+            elapsed,
+            duration,
+            duration_known: duration > 0,
+            art_url: song.item.track.art_url().map(String::from),
         })
     }
 }
 
+/// Compute the elapsed and total seconds reported for a song.
+///
+/// Elapsed is clamped to the duration so that progress never overshoots. A
+/// zero duration means that the duration is unknown, like for live streams, in
+/// which case elapsed is reported as is.
+fn progress(elapsed: Duration, duration: Duration) -> (u64, u64) {
+    let duration = duration.as_secs();
+    let elapsed = elapsed.as_secs();
+
+    if duration > 0 {
+        (u64::min(elapsed, duration), duration)
+    } else {
+        (elapsed, duration)
+    }
+}
+
+/// A raw message which isn't modelled by any of the message enums, for
+/// custom events.
+///
+/// Serialized exactly as its value.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(transparent)]
+pub struct Raw {
+    /// The key under which the message is cached, if any.
+    #[serde(skip)]
+    pub id: Option<&'static str>,
+    /// The value of the message.
+    pub value: serde_json::Value,
+}
+
+impl Raw {
+    /// Construct a new raw message, cached under `id` if specified.
+    pub fn new(id: Option<&'static str>, value: serde_json::Value) -> Self {
+        Self { id, value }
+    }
+}
+
+impl Message for Raw {
+    fn id(&self) -> Option<MessageId> {
+        self.id.map(MessageId::Custom)
+    }
+}
+
 /// Events for running commands externally.
 #[derive(Debug, Clone, serde::Serialize)]
 #[serde(tag = "type")]
@@ -231,7 +2075,609 @@ pub enum Command {
 
 impl Message for Command {
     /// Whether a message should be cached or not and under what key.
-    fn id(&self) -> Option<&'static str> {
+    fn id(&self) -> Option<MessageId> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        frame, merge, progress, Bus, BusDecoder, BusError, Deltas, Global, Message as _, MessageId,
+        Priority, Raw, CAPACITY,
+    };
+    use futures_util::StreamExt as _;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    #[test]
+    fn test_progress_clamps_elapsed() {
+        assert_eq!(
+            (10, 20),
+            progress(Duration::from_secs(10), Duration::from_secs(20))
+        );
+        assert_eq!(
+            (20, 20),
+            progress(Duration::from_secs(30), Duration::from_secs(20))
+        );
+        assert_eq!(
+            (5, 0),
+            progress(Duration::from_secs(5), Duration::from_secs(0))
+        );
+    }
+
+    #[test]
+    fn test_song_zero_duration() {
+        use crate::api::youtube::Video;
+        use crate::player::{Item, Song, Track};
+        use crate::track_id::TrackId;
+        use std::sync::Arc;
+
+        let item = Arc::new(Item {
+            track_id: TrackId::YouTube(String::from("live")),
+            track: Track::YouTube {
+                video: Video {
+                    kind: String::from("youtube#video"),
+                    etag: String::new(),
+                    id: String::from("live"),
+                    snippet: None,
+                    content_details: None,
+                },
+            },
+            user: None,
+            duration: Duration::from_secs(0),
+        });
+
+        let mut song = Song::new(item, Duration::from_secs(30));
+        song.play();
+
+        match Global::song(Some(&song)).unwrap() {
+            Global::SongCurrent {
+                is_playing: true,
+                elapsed: 30,
+                duration: 0,
+                duration_known: false,
+                art_url: None,
+                ..
+            } => (),
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        match Global::song_progress(Some(&song)) {
+            Global::SongProgress {
+                elapsed: 30,
+                duration_known: false,
+                ..
+            } => (),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_subscribe_with_snapshot() {
+        let bus = Bus::new();
+        futures_executor::block_on(bus.send(Global::volume("spotify", 10)));
+
+        let (snapshot, mut reader) = bus.subscribe_with_snapshot();
+        futures_executor::block_on(bus.send(Global::volume("spotify", 20)));
+
+        match snapshot.as_slice() {
+            [Global::Volume { volume: 10, .. }] => (),
+            other => panic!("unexpected snapshot: {:?}", other),
+        }
+
+        match futures_executor::block_on(reader.recv()) {
+            Ok(Global::Volume { volume: 20, .. }) => (),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_latest_max_age() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            tokio::time::pause();
+
+            let bus = Bus::<Global>::builder()
+                .max_age(Duration::from_millis(50))
+                .build();
+            bus.send(Global::volume("spotify", 10)).await;
+            assert_eq!(1, bus.latest().await.len());
+
+            tokio::time::advance(Duration::from_millis(100)).await;
+            assert!(bus.latest().await.is_empty());
+
+            bus.send(Global::volume("spotify", 20)).await;
+            let (snapshot, _) = bus.subscribe_with_snapshot();
+            assert_eq!(1, snapshot.len());
+        });
+    }
+
+    #[test]
+    fn test_cached_keys() {
+        let bus = Bus::new();
+        assert!(bus.cached_keys().is_empty());
+
+        futures_executor::block_on(bus.send(Global::volume("spotify", 10)));
+        futures_executor::block_on(bus.send(Global::SongModified));
+        assert_eq!(vec!["volume"], bus.cached_keys());
+    }
+
+    #[test]
+    fn test_raw() {
+        let bus = Bus::new();
+        let value = serde_json::json!({"type": "custom/thing", "value": 42});
+        futures_executor::block_on(bus.send(Raw::new(Some("custom/thing"), value)));
+
+        assert_eq!(vec!["custom/thing"], bus.cached_keys());
+
+        let m = futures_executor::block_on(bus.latest_by_key("custom/thing")).unwrap();
+        assert_eq!(
+            r#"{"type":"custom/thing","value":42}"#,
+            bus.preview(&m).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_effect() {
+        let bus = Bus::new();
+        let params = serde_json::json!({"color": "red"});
+        let m = Global::effect("confetti", Duration::from_secs(5), params);
+
+        assert_eq!(
+            r#"{"type":"effect","name":"confetti","duration_ms":5000,"params":{"color":"red"}}"#,
+            bus.preview(&m).unwrap()
+        );
+
+        futures_executor::block_on(bus.send(m));
+        assert!(bus.cached_keys().is_empty());
+    }
+
+    #[test]
+    fn test_serialize_event() {
+        let bus = Bus::<Global>::builder()
+            .pretty(true)
+            .flag_replays(true)
+            .build();
+        let m = Global::volume("spotify", 10);
+
+        let (ty, json) = bus.serialize_event(true, &m).unwrap().unwrap();
+        assert_eq!(Some("volume"), ty.as_deref());
+        assert!(!json.contains('\n'));
+        assert_eq!(
+            serde_json::json!({"type": "volume", "player": "spotify", "volume": 10, "replay": true}),
+            serde_json::from_str::<serde_json::Value>(&json).unwrap()
+        );
+
+        let raw = Raw::new(None, serde_json::json!([1, 2]));
+        let bus = Bus::new();
+
+        assert_eq!(
+            Some((None, String::from("[1,2]"))),
+            bus.serialize_event(false, &raw).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_moderation() {
+        let bus = Bus::new();
+
+        assert_eq!(
+            r##"{"type":"chat/clear","channel":"#setmod"}"##,
+            bus.preview(&Global::chat_clear("#setmod")).unwrap()
+        );
+        assert_eq!(
+            r##"{"type":"chat/timeout","channel":"#setmod","user":"foo","duration":600}"##,
+            bus.preview(&Global::user_timeout("#setmod", "foo", 600))
+                .unwrap()
+        );
+        assert_eq!(
+            r##"{"type":"chat/ban","channel":"#setmod","user":"foo"}"##,
+            bus.preview(&Global::user_ban("#setmod", "foo")).unwrap()
+        );
+
+        futures_executor::block_on(bus.send(Global::user_ban("#setmod", "foo")));
+        assert!(bus.cached_keys().is_empty());
+    }
+
+    #[test]
+    fn test_max_cached() {
+        let bus = Bus::<Raw>::builder().max_cached(2).build();
+        bus.pin_key(MessageId::Custom("custom/pinned"));
+
+        for key in &["custom/pinned", "custom/a", "custom/b"] {
+            futures_executor::block_on(bus.send(Raw::new(Some(*key), serde_json::Value::Null)));
+        }
+
+        let mut keys = bus.cached_keys();
+        keys.sort();
+        assert_eq!(vec!["custom/b", "custom/pinned"], keys);
+    }
+
+    #[test]
+    fn test_emit_health() {
+        let bus = Bus::new();
+        let _reader = bus.subscribe();
+        futures_executor::block_on(bus.emit_health());
+
+        match futures_executor::block_on(bus.latest_by_key("health")) {
+            Some(Global::Health {
+                receivers: 1,
+                dropped: 0,
+                ..
+            }) => (),
+            other => panic!("unexpected health: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_metrics_text() {
+        let bus = Bus::new();
+        let _reader = bus.subscribe();
+        futures_executor::block_on(bus.send(Global::volume("spotify", 10)));
+        futures_executor::block_on(bus.send(Global::SongModified));
+
+        let text = bus.metrics_text();
+        assert!(text.contains("# TYPE setmod_bus_sent_total counter\nsetmod_bus_sent_total 2\n"));
+        assert!(text.contains("\nsetmod_bus_receivers 1\n"));
+        assert!(text.contains("\nsetmod_bus_lagging 0\n"));
+        assert!(text.contains("\nsetmod_bus_cached 1\n"));
+    }
+
+    #[test]
+    fn test_byte_stats() {
+        let bus = Bus::new();
+        let m = Global::volume("spotify", 10);
+        let len = bus.serialize(None, &m).unwrap().unwrap().len() as u64;
+        bus.serialize(None, &m).unwrap();
+        bus.serialize(None, &Global::SongModified).unwrap();
+
+        let stats = bus.byte_stats();
+        assert_eq!(1, stats.len());
+        assert_eq!(Some(&(len * 2)), stats.get("volume"));
+    }
+
+    #[test]
+    fn test_send_all() {
+        let bus = Bus::new();
+        let mut reader = bus.subscribe();
+
+        futures_executor::block_on(bus.send_all(vec![
+            Global::volume("spotify", 10),
+            Global::SongModified,
+            Global::volume("spotify", 20),
+        ]));
+
+        match futures_executor::block_on(bus.latest()).as_slice() {
+            [Global::Volume { volume: 20, .. }] => (),
+            other => panic!("unexpected latest: {:?}", other),
+        }
+
+        for expected in &[1, 2, 3] {
+            let (seq, _) = futures_executor::block_on(reader.recv_seq()).unwrap();
+            assert_eq!(*expected, seq);
+        }
+    }
+
+    #[test]
+    fn test_send_if_absent() {
+        let bus = Bus::new();
+        assert!(futures_executor::block_on(
+            bus.send_if_absent(Global::volume("spotify", 10))
+        ));
+        assert!(!futures_executor::block_on(
+            bus.send_if_absent(Global::volume("spotify", 20))
+        ));
+
+        match futures_executor::block_on(bus.latest()).as_slice() {
+            [Global::Volume { volume: 10, .. }] => (),
+            other => panic!("unexpected latest: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_send_delta() {
+        let bus = Bus::new();
+        let mut reader = bus.subscribe();
+
+        futures_executor::block_on(bus.send_delta(
+            Global::SongStateChanged {
+                is_playing: false,
+                elapsed: 10,
+            },
+            Global::volume("spotify", 10),
+        ));
+
+        match futures_executor::block_on(reader.recv()) {
+            Ok(Global::SongStateChanged {
+                is_playing: false,
+                elapsed: 10,
+            }) => (),
+            other => panic!("unexpected message: {:?}", other),
+        }
+
+        match futures_executor::block_on(bus.latest()).as_slice() {
+            [Global::Volume { volume: 10, .. }] => (),
+            other => panic!("unexpected latest: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_song_art_url() {
+        use crate::player::{Item, Song, Track};
+        use crate::track_id::TrackId;
+        use std::sync::Arc;
+
+        let video = serde_json::from_value(serde_json::json!({
+            "kind": "youtube#video",
+            "etag": "",
+            "id": "video",
+            "snippet": {
+                "channelId": "channel",
+                "title": "Video",
+                "thumbnails": {
+                    "default": {"url": "https://example.com/default.jpg", "width": 120, "height": 90},
+                    "high": {"url": "https://example.com/high.jpg", "width": 480, "height": 360},
+                },
+            },
+        }))
+        .unwrap();
+
+        let item = Arc::new(Item {
+            track_id: TrackId::YouTube(String::from("video")),
+            track: Track::YouTube { video },
+            user: None,
+            duration: Duration::from_secs(60),
+        });
+
+        let song = Song::new(item, Duration::from_secs(0));
+
+        match Global::song(Some(&song)).unwrap() {
+            Global::SongCurrent {
+                art_url: Some(art_url),
+                ..
+            } => assert_eq!("https://example.com/high.jpg", art_url),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_serialize_delta() {
+        let bus = Bus::new();
+        let mut deltas = Deltas::default();
+
+        let mut delta = |m: &Global| -> Option<serde_json::Value> {
+            let json = bus.serialize_delta(&mut deltas, None, m).unwrap()?;
+            Some(serde_json::from_str(&json).unwrap())
+        };
+
+        assert_eq!(
+            Some(serde_json::json!({"type": "volume", "player": "spotify", "volume": 10})),
+            delta(&Global::volume("spotify", 10))
+        );
+        assert_eq!(
+            Some(serde_json::json!({"type": "volume", "volume": 20, "delta": true})),
+            delta(&Global::volume("spotify", 20))
+        );
+        assert_eq!(None, delta(&Global::volume("spotify", 20)));
+        assert_eq!(
+            Some(serde_json::json!({"type": "song/modified"})),
+            delta(&Global::SongModified)
+        );
+    }
+
+    #[test]
+    fn test_decoder() {
+        for pretty in &[false, true] {
+            let bus = Bus::<Global>::builder().pretty(*pretty).build();
+
+            let batch = vec![
+                bus.serialize(Some(1), &Global::volume("spotify", 10))
+                    .unwrap()
+                    .unwrap(),
+                bus.serialize(Some(2), &Global::SongModified)
+                    .unwrap()
+                    .unwrap(),
+            ];
+
+            let decoded = BusDecoder::new()
+                .length_prefixed(*pretty)
+                .decode(&frame(&batch, *pretty))
+                .unwrap();
+
+            assert_eq!(2, decoded.len());
+            assert_eq!(Some("volume"), decoded[0]["type"].as_str());
+            assert_eq!(Some(2), decoded[1]["seq"].as_u64());
+        }
+    }
+
+    #[test]
+    fn test_latest_etag() {
+        let bus = Bus::new();
+        let etag = bus.latest_etag();
+
+        futures_executor::block_on(bus.send(Global::SongModified));
+        assert_eq!(etag, bus.latest_etag());
+
+        futures_executor::block_on(bus.send(Global::volume("spotify", 10)));
+        let updated = bus.latest_etag();
+        assert_ne!(etag, updated);
+        assert_eq!(updated, bus.latest_etag());
+    }
+
+    #[test]
+    fn test_connection_cleanup() {
+        let bus = Bus::<Global>::new();
+        let receivers = bus.receiver_count();
+
+        {
+            let _connection = bus.connect();
+            let _reader = bus.subscribe();
+            assert_eq!(1, bus.connection_count());
+            assert_eq!(receivers + 1, bus.receiver_count());
+        }
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _connection = bus.connect();
+            let _reader = bus.subscribe();
+            panic!("connection failed");
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(0, bus.connection_count());
+        assert_eq!(receivers, bus.receiver_count());
+    }
+
+    #[test]
+    fn test_connected_peers() {
+        let bus = Bus::<Global>::new();
+        let a = SocketAddr::from(([127, 0, 0, 1], 1000));
+        let b = SocketAddr::from(([10, 0, 0, 1], 2000));
+
+        let first = bus.connect_from(Some(a));
+        let _unknown = bus.connect();
+
+        {
+            let _second = bus.connect_from(Some(b));
+            assert_eq!(vec![a, b], bus.connected_peers());
+        }
+
+        assert_eq!(vec![a], bus.connected_peers());
+        drop(first);
+        assert!(bus.connected_peers().is_empty());
+    }
+
+    #[test]
+    fn test_recv_high_first() {
+        let bus = Bus::<Global>::new();
+        let mut reader = bus.subscribe();
+
+        futures_executor::block_on(bus.send_all(vec![
+            Global::volume("spotify", 10),
+            Global::Firework,
+            Global::volume("spotify", 20),
+        ]));
+
+        let mut received = Vec::new();
+
+        for _ in 0..3 {
+            let (seq, m) = futures_executor::block_on(reader.recv_seq()).unwrap();
+            received.push((seq, m.priority(), reader.resume_seq()));
+        }
+
+        assert_eq!(
+            vec![
+                (2, Priority::High, 0),
+                (1, Priority::Normal, 2),
+                (3, Priority::Normal, 3)
+            ],
+            received
+        );
+    }
+
+    #[test]
+    fn test_recv_high_while_flooded() {
+        let bus = Bus::<Global>::new();
+        let mut reader = bus.subscribe();
+
+        let mut flood = (0..CAPACITY as u32 * 2)
+            .map(|volume| Global::volume("spotify", volume))
+            .collect::<Vec<_>>();
+
+        flood.push(Global::Firework);
+        futures_executor::block_on(bus.send_all(flood));
+
+        assert!(matches!(
+            futures_executor::block_on(reader.recv()),
+            Ok(Global::Firework)
+        ));
+        assert!(matches!(
+            futures_executor::block_on(reader.recv()),
+            Err(BusError::Lagged(..))
+        ));
+    }
+
+    #[test]
+    fn test_merge() {
+        let a = Bus::new();
+        let b = Bus::new();
+        let mut merged = merge(vec![a.subscribe(), b.subscribe()]);
+
+        futures_executor::block_on(a.send(Global::Ping));
+        let m = futures_executor::block_on(merged.next());
+        assert!(matches!(m, Some(Global::Ping)));
+
+        a.shutdown();
+        futures_executor::block_on(b.send(Global::Firework));
+        let m = futures_executor::block_on(merged.next());
+        assert!(matches!(m, Some(Global::Firework)));
+
+        b.shutdown();
+        assert!(futures_executor::block_on(merged.next()).is_none());
+    }
+
+    #[test]
+    fn test_subscribe_bounded() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let bus = Bus::new();
+            let mut reader = bus.subscribe_bounded(2);
+
+            for n in 0..5 {
+                bus.send(Raw::new(None, serde_json::Value::from(n))).await;
+            }
+
+            // Give the background task a chance to drain the bus.
+            tokio::time::sleep(Duration::from_millis(10)).await;
+
+            assert!(matches!(reader.recv().await, Err(BusError::Lagged(3))));
+            assert_eq!(
+                serde_json::Value::from(3),
+                reader.recv().await.unwrap().value
+            );
+            assert_eq!(
+                serde_json::Value::from(4),
+                reader.recv().await.unwrap().value
+            );
+
+            bus.shutdown();
+            assert!(matches!(reader.recv().await, Err(BusError::Closed)));
+        });
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let bus = Bus::<Global>::new();
+        let retry_after = bus.retry_after();
+        assert!(retry_after >= Duration::from_secs(1));
+        assert!(retry_after <= Duration::from_millis(1500));
+
+        let _readers = (0..1000).map(|_| bus.subscribe()).collect::<Vec<_>>();
+        let retry_after = bus.retry_after();
+        assert!(retry_after >= Duration::from_secs(30));
+        assert!(retry_after <= Duration::from_secs(45));
+    }
+
+    #[test]
+    fn test_flag_replays() {
+        let m = Global::volume("spotify", 10);
+
+        let bus = Bus::<Global>::builder().flag_replays(true).build();
+        let live = bus.serialize(Some(1), &m).unwrap().unwrap();
+        let replay = bus.serialize_replay(&m).unwrap().unwrap();
+        assert!(live.contains("\"replay\":false"));
+        assert!(replay.contains("\"replay\":true"));
+
+        let bus = Bus::new();
+        let replay = bus.serialize_replay(&m).unwrap().unwrap();
+        assert!(!replay.contains("replay"));
+    }
+}
@@ -88,6 +88,7 @@ pub struct Aliases {
 
 impl Aliases {
     database_group_fns!(Alias, db::Key);
+    database_matcher_fns!(Alias);
 
     /// Construct a new commands store with a db.
     pub async fn load(db: db::Database) -> Result<Aliases, anyhow::Error> {
@@ -95,9 +96,13 @@ impl Aliases {
 
         let db = Database(db);
 
-        for alias in db.list().await? {
-            let alias = Alias::from_db(&alias)?;
-            inner.insert(alias.key.clone(), Arc::new(alias));
+        let errors = inner.insert_all(db.list().await?.iter().map(|alias| {
+            let key = db::Key::new(&alias.channel, &alias.name);
+            (key, Alias::from_db(alias))
+        }));
+
+        for (key, e) in errors {
+            log_error!(e, "failed to load alias `{}`", key);
         }
 
         Ok(Aliases {
@@ -190,9 +195,17 @@ impl db::Matchable for Alias {
         &self.key
     }
 
+    fn key_mut(&mut self) -> &mut db::Key {
+        &mut self.key
+    }
+
     fn pattern(&self) -> &db::Pattern {
         &self.pattern
     }
+
+    fn description(&self) -> Option<&str> {
+        Some(self.template.source())
+    }
 }
 
 impl Alias {
@@ -3,10 +3,11 @@ use crate::template;
 use crate::utils;
 use anyhow::{anyhow, Context as _, Error};
 use diesel::prelude::*;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time;
 use tokio::sync::RwLock;
 
 /// Local database wrapper.
@@ -38,6 +39,7 @@ impl Database {
                             text: text.to_string(),
                             group: None,
                             disabled: false,
+                            delay: None,
                         };
 
                         diesel::insert_into(dsl::commands)
@@ -83,6 +85,31 @@ impl Database {
             .await
     }
 
+    /// Edit the delay of a command.
+    async fn edit_delay(
+        &self,
+        key: &db::Key,
+        delay: Option<utils::Duration>,
+    ) -> Result<(), anyhow::Error> {
+        use db::schema::commands::dsl;
+
+        let key = key.clone();
+        let delay = delay.map(|delay| delay.as_std().as_secs() as i32);
+
+        self.0
+            .asyncify(move |c| {
+                diesel::update(
+                    dsl::commands
+                        .filter(dsl::channel.eq(&key.channel).and(dsl::name.eq(&key.name))),
+                )
+                .set(dsl::delay.eq(delay))
+                .execute(c)?;
+
+                Ok(())
+            })
+            .await
+    }
+
     /// Increment the given key.
     async fn increment(&self, key: &db::Key) -> Result<bool, Error> {
         use db::schema::commands::dsl;
@@ -111,6 +138,7 @@ pub struct Commands {
 
 impl Commands {
     database_group_fns!(Command, db::Key);
+    database_matcher_fns!(Command);
 
     /// Construct a new commands store with a db.
     pub async fn load(db: db::Database) -> Result<Commands, Error> {
@@ -118,9 +146,13 @@ impl Commands {
 
         let mut matcher = db::Matcher::new();
 
-        for command in db.list().await? {
-            let command = Command::from_db(&command)?;
-            matcher.insert(command.key.clone(), Arc::new(command));
+        let errors = matcher.insert_all(db.list().await?.iter().map(|command| {
+            let key = db::Key::new(&command.channel, &command.name);
+            (key, Command::from_db(command))
+        }));
+
+        for (key, e) in errors {
+            log_error!(e, "failed to load command `{}`", key);
         }
 
         Ok(Commands {
@@ -154,6 +186,7 @@ impl Commands {
                 vars,
                 group: command.group,
                 disabled: command.disabled,
+                delay: delay_from_db(command.delay),
             });
 
             inner.insert(key, command);
@@ -177,6 +210,21 @@ impl Commands {
         }))
     }
 
+    /// Edit the delay before responding to the given command.
+    pub async fn edit_delay(
+        &self,
+        channel: &str,
+        name: &str,
+        delay: Option<utils::Duration>,
+    ) -> Result<bool, anyhow::Error> {
+        let key = db::Key::new(channel, name);
+        self.db.edit_delay(&key, delay).await?;
+
+        Ok(self.inner.write().await.modify(key, |command| {
+            command.delay = delay;
+        }))
+    }
+
     /// Increment the specified command.
     pub async fn increment(&self, command: &Command) -> Result<(), Error> {
         self.db.increment(&command.key).await?;
@@ -184,19 +232,162 @@ impl Commands {
         Ok(())
     }
 
-    /// Resolve the given command.
+    /// Resolve the given command, together with how long to wait before
+    /// responding to it.
     pub async fn resolve<'a>(
         &self,
         channel: &'a str,
         first: Option<&'a str>,
         it: &'a utils::Words,
-    ) -> Option<(Arc<Command>, db::Captures<'a>)> {
+    ) -> Option<(Arc<Command>, db::Captures<'a>, Option<time::Duration>)> {
         let inner = self.inner.read().await;
 
         inner
             .resolve(channel, first, it)
+            .map(|(command, captures)| {
+                let delay = db::Matchable::delay(&**command);
+                (command.clone(), captures, delay)
+            })
+    }
+
+    /// Resolve the given command, failing if the input matches more than one
+    /// pattern.
+    pub async fn resolve_checked<'a>(
+        &self,
+        channel: &'a str,
+        first: Option<&'a str>,
+        it: &'a utils::Words,
+    ) -> Result<Option<(Arc<Command>, db::Captures<'a>)>, db::ResolveError> {
+        let inner = self.inner.read().await;
+
+        Ok(inner
+            .resolve_checked(channel, first, it)?
+            .map(|(command, captures)| (command.clone(), captures)))
+    }
+
+    /// Resolve the given command, with captures which don't borrow from the
+    /// input.
+    pub async fn resolve_owned(
+        &self,
+        channel: &str,
+        first: Option<&str>,
+        it: &utils::Words,
+    ) -> Option<(Arc<Command>, db::OwnedCaptures)> {
+        self.inner.read().await.resolve_owned(channel, first, it)
+    }
+
+    /// Resolve the given command, with captures keyed by the parameter names
+    /// declared by the command.
+    pub async fn resolve_params<'a>(
+        &self,
+        channel: &'a str,
+        first: Option<&'a str>,
+        it: &'a utils::Words,
+    ) -> Option<(Arc<Command>, HashMap<String, &'a str>)> {
+        let inner = self.inner.read().await;
+
+        inner
+            .resolve_params(channel, first, it)
+            .map(|(command, params)| (command.clone(), params))
+    }
+
+    /// Resolve the given command, only considering commands in one of the
+    /// allowed groups.
+    pub async fn resolve_filtered<'a>(
+        &self,
+        channel: &'a str,
+        first: Option<&'a str>,
+        it: &'a utils::Words,
+        allowed_groups: &HashSet<String>,
+    ) -> Option<(Arc<Command>, db::Captures<'a>)> {
+        let inner = self.inner.read().await;
+
+        inner
+            .resolve_filtered(channel, first, it, allowed_groups)
             .map(|(command, captures)| (command.clone(), captures))
     }
+
+    /// Resolve the given command, where the first word is known to already
+    /// be lowercase.
+    pub async fn resolve_lower<'a>(
+        &self,
+        channel: &'a str,
+        first: Option<&'a str>,
+        it: &'a utils::Words,
+    ) -> Option<(Arc<Command>, db::Captures<'a>)> {
+        let inner = self.inner.read().await;
+
+        inner
+            .resolve_lower(channel, first, it)
+            .map(|(command, captures)| (command.clone(), captures))
+    }
+
+    /// Resolve the given command, also matching unique prefixes of command
+    /// names.
+    pub async fn resolve_prefix<'a>(
+        &self,
+        channel: &'a str,
+        first: Option<&'a str>,
+        it: &'a utils::Words,
+    ) -> Option<(Arc<Command>, db::Captures<'a>)> {
+        let inner = self.inner.read().await;
+
+        inner
+            .resolve_prefix(channel, first, it)
+            .map(|(command, captures)| (command.clone(), captures))
+    }
+
+    /// Explain which command the given input would resolve to, without
+    /// running it.
+    pub async fn explain<'a>(
+        &self,
+        channel: &str,
+        first: Option<&'a str>,
+        it: &'a utils::Words,
+    ) -> db::Explanation<'a> {
+        self.inner.read().await.explain(channel, first, it)
+    }
+
+    /// Resolve the given command, matching patterns against a cleaned up
+    /// version of the input.
+    pub async fn resolve_with_clean<'a>(
+        &self,
+        channel: &'a str,
+        first: Option<&'a str>,
+        clean: &'a str,
+        it: &'a utils::Words,
+    ) -> Option<(Arc<Command>, db::Captures<'a>)> {
+        let inner = self.inner.read().await;
+
+        inner
+            .resolve_with_clean(channel, first, clean, it)
+            .map(|(command, captures)| (command.clone(), captures))
+    }
+
+    /// Resolve the given input against commands in the given namespace only.
+    pub async fn resolve_in_namespace<'a>(
+        &self,
+        channel: &str,
+        namespace: Option<&str>,
+        full: &'a str,
+    ) -> Option<(Arc<Command>, db::Captures<'a>)> {
+        let inner = self.inner.read().await;
+
+        inner
+            .resolve_in_namespace(channel, namespace, full)
+            .map(|(command, captures)| (command.clone(), captures))
+    }
+
+    /// Enable or disable every command in the given channel, without
+    /// persisting the change.
+    ///
+    /// Returns the number of commands affected.
+    pub async fn set_channel_enabled(&self, channel: &str, enabled: bool) -> usize {
+        self.inner
+            .write()
+            .await
+            .set_channel_enabled(channel, enabled)
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -212,6 +403,15 @@ pub struct Command {
     vars: HashSet<String>,
     pub group: Option<String>,
     pub disabled: bool,
+    /// How long to wait before responding to the command.
+    pub delay: Option<utils::Duration>,
+}
+
+/// Convert a delay stored in the database.
+fn delay_from_db(delay: Option<i32>) -> Option<utils::Duration> {
+    delay
+        .filter(|delay| *delay > 0)
+        .map(|delay| utils::Duration::seconds(delay as u64))
 }
 
 /// Serialize the atomic count.
@@ -248,6 +448,7 @@ impl Command {
             vars,
             group: command.group.clone(),
             disabled: command.disabled,
+            delay: delay_from_db(command.delay),
         })
     }
 
@@ -275,20 +476,102 @@ impl db::Matchable for Command {
         &self.key
     }
 
+    fn key_mut(&mut self) -> &mut db::Key {
+        &mut self.key
+    }
+
     fn pattern(&self) -> &db::Pattern {
         &self.pattern
     }
+
+    fn description(&self) -> Option<&str> {
+        Some(self.template.source())
+    }
+
+    fn delay(&self) -> Option<time::Duration> {
+        self.delay.map(|delay| delay.as_std())
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.disabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.disabled = !enabled;
+    }
+
+    /// The group of a command is its only tag.
+    fn tags(&self) -> &[String] {
+        match &self.group {
+            Some(group) => std::slice::from_ref(group),
+            None => &[],
+        }
+    }
 }
 
 impl fmt::Display for Command {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             fmt,
-            "template = \"{template}\", pattern = {pattern}, group = {group}, disabled = {disabled}",
+            "template = \"{template}\", pattern = {pattern}, group = {group}, disabled = {disabled}, delay = {delay}",
             template = self.template,
             pattern = self.pattern,
             group = self.group.as_deref().unwrap_or("*none*"),
             disabled = self.disabled,
+            delay = self.delay.map(|d| d.to_string()).as_deref().unwrap_or("*none*"),
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Commands;
+    use crate::db;
+    use crate::template;
+    use crate::utils;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    async fn delay(commands: &Commands, name: &str) -> Option<Duration> {
+        let it = utils::Words::new(Arc::new(String::new()));
+        let (_, _, delay) = commands.resolve("#test", Some(name), &it).await.unwrap();
+        delay
+    }
+
+    #[test]
+    fn test_resolve_delay() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let db = db::Database::open(Path::new(":memory:")).unwrap();
+            let commands = Commands::load(db.clone()).await.unwrap();
+
+            for name in &["!slow", "!fast"] {
+                let template = template::Template::compile("Hello!").unwrap();
+                commands.edit("#test", name, template).await.unwrap();
+            }
+
+            let slow = Some(utils::Duration::seconds(5));
+            assert!(commands.edit_delay("#test", "!slow", slow).await.unwrap());
+            assert_eq!(
+                Some(Duration::from_secs(5)),
+                delay(&commands, "!slow").await
+            );
+            assert_eq!(None, delay(&commands, "!fast").await);
+
+            // The delay is persisted.
+            let commands = Commands::load(db).await.unwrap();
+            assert_eq!(
+                Some(Duration::from_secs(5)),
+                delay(&commands, "!slow").await
+            );
+
+            assert!(commands.edit_delay("#test", "!slow", None).await.unwrap());
+            assert_eq!(None, delay(&commands, "!slow").await);
+        });
+    }
+}
@@ -70,19 +70,10 @@ macro_rules! database_group_fns {
         pub async fn disable_group(&self, channel: &str, group: &str) -> Result<(), anyhow::Error> {
             self.db.set_group_disabled(channel, group, true).await?;
 
-            let mut inner = self.inner.write().await;
-
-            let mut to_delete = Vec::new();
-
-            for (key, value) in inner.iter() {
-                if value.group.as_deref() == Some(group) {
-                    to_delete.push(key.clone());
-                }
-            }
-
-            for key in to_delete {
-                inner.remove(&key);
-            }
+            self.inner
+                .write()
+                .await
+                .retain(|_, value| value.group.as_deref() != Some(group));
 
             Ok(())
         }
@@ -153,7 +144,13 @@ macro_rules! database_group_fns {
 
             out
         }
+    };
+}
 
+/// Helper macro to build the database function for renaming things in groups
+/// which are not backed by a matcher.
+macro_rules! database_rename_fn {
+    ($thing:ty, $key:ty) => {
         /// Try to rename the thing.
         pub async fn rename(
             &self,
@@ -203,6 +200,297 @@ macro_rules! database_group_fns {
     };
 }
 
+/// Helper macro to build database functions for groups backed by a matcher.
+macro_rules! database_matcher_fns {
+    ($thing:ty) => {
+        /// Try to rename the thing.
+        ///
+        /// A temporary thing keeps expiring at the same time.
+        pub async fn rename(
+            &self,
+            channel: &str,
+            from: &str,
+            to: &str,
+        ) -> Result<(), super::RenameError> {
+            let from_key = db::Key::new(channel, from);
+            let to_key = db::Key::new(channel, to);
+
+            self.inner.write().await.rename(&from_key, to_key.clone())?;
+
+            match self.db.rename(&from_key, &to_key).await {
+                Err(e) => {
+                    log::error!(
+                        "failed to rename {what} `{}` in database: {}",
+                        from,
+                        e,
+                        what = <$thing>::NAME
+                    );
+                }
+                Ok(false) => {
+                    log::warn!(
+                        "{what} {} not renamed in database",
+                        from,
+                        what = <$thing>::NAME
+                    );
+                }
+                Ok(true) => (),
+            }
+
+            Ok(())
+        }
+
+        /// Reload every thing from the database.
+        ///
+        /// The loaded things are swapped in all at once, so a concurrent
+        /// resolve never observes a partially loaded set. Things which fail to
+        /// load are logged and skipped.
+        pub async fn reload(&self) -> Result<(), anyhow::Error> {
+            let mut things = Vec::new();
+
+            for thing in self.db.list().await? {
+                match <$thing>::from_db(&thing) {
+                    Ok(thing) => things.push((thing.key.clone(), Arc::new(thing))),
+                    Err(e) => {
+                        log_error!(
+                            e,
+                            "failed to load {what} `{}/{}`",
+                            thing.channel,
+                            thing.name,
+                            what = <$thing>::NAME
+                        );
+                    }
+                }
+            }
+
+            self.inner.write().await.replace_all(things);
+            Ok(())
+        }
+
+        /// Move every thing in channel `from` to channel `to`.
+        ///
+        /// Returns the number of things moved.
+        pub async fn migrate_channel(
+            &self,
+            from: &str,
+            to: &str,
+            collision: db::Collision,
+        ) -> Result<usize, super::RenameError> {
+            let mut inner = self.inner.write().await;
+            let moved = inner.migrate_channel(from, to, collision)?;
+
+            for (from_key, to_key) in &moved {
+                match self.db.rename(from_key, to_key).await {
+                    Err(e) => {
+                        log::error!(
+                            "failed to migrate {what} `{}` in database: {}",
+                            from_key,
+                            e,
+                            what = <$thing>::NAME
+                        );
+                    }
+                    Ok(false) => {
+                        log::warn!(
+                            "{what} {} not migrated in database",
+                            from_key,
+                            what = <$thing>::NAME
+                        );
+                    }
+                    Ok(true) => (),
+                }
+            }
+
+            Ok(moved.len())
+        }
+
+        /// Make the given thing temporary, so that it's removed once
+        /// `expires_at` has passed.
+        ///
+        /// When it expires isn't stored in the database, so a thing which
+        /// hasn't expired yet when the bot is restarted is kept.
+        ///
+        /// Returns `false` if there is no such thing.
+        pub async fn expire(
+            &self,
+            channel: &str,
+            name: &str,
+            expires_at: std::time::Instant,
+        ) -> bool {
+            let key = db::Key::new(channel, name);
+            let mut inner = self.inner.write().await;
+
+            let thing = match inner.get(&key) {
+                Some(thing) => Arc::clone(thing),
+                None => return false,
+            };
+
+            inner.insert_expiring(key, thing, expires_at);
+            true
+        }
+
+        /// Temporarily shadow the thing with the same key with the given one,
+        /// until the override is [popped][Self::pop_override].
+        ///
+        /// Overrides are not stored in the database.
+        pub async fn push_override(&self, thing: $thing) {
+            let key = thing.key.clone();
+            self.inner.write().await.push_override(key, Arc::new(thing));
+        }
+
+        /// Remove the most recent override of the given thing, restoring the
+        /// thing it shadowed.
+        ///
+        /// Returns the removed override, if there was one.
+        pub async fn pop_override(&self, channel: &str, name: &str) -> Option<Arc<$thing>> {
+            let key = db::Key::new(channel, name);
+            self.inner.write().await.pop_override(&key)
+        }
+
+        /// Remove every temporary thing which has expired, including from
+        /// the database.
+        ///
+        /// Returns the keys of the removed things.
+        pub async fn sweep_expired(&self) -> Result<Vec<db::Key>, anyhow::Error> {
+            let expired = self
+                .inner
+                .write()
+                .await
+                .sweep_expired(std::time::Instant::now());
+
+            for key in &expired {
+                self.db.delete(key).await?;
+            }
+
+            Ok(expired)
+        }
+
+        /// Get a list of all things in the channel, sorted by name.
+        pub async fn list_sorted(&self, channel: &str) -> Vec<Arc<$thing>> {
+            let inner = self.inner.read().await;
+
+            inner
+                .iter_sorted()
+                .into_iter()
+                .filter(|(key, _)| key.channel == channel)
+                .map(|(_, thing)| Arc::clone(thing))
+                .collect()
+        }
+
+        /// Get the given thing by name, together with how it's matched.
+        ///
+        /// A thing which isn't indexed according to its pattern is treated as
+        /// absent.
+        pub async fn get_with_kind(
+            &self,
+            channel: &str,
+            name: &str,
+        ) -> Option<(Arc<$thing>, db::MatchKind)> {
+            let key = db::Key::new(channel, name);
+            let inner = self.inner.read().await;
+            let (thing, kind) = inner.get_with_kind(&key)?;
+            Some((Arc::clone(thing), kind))
+        }
+
+        /// Get up to `limit` names of things in the channel which are closest to
+        /// `input`, closest first.
+        pub async fn suggest(&self, channel: &str, input: &str, limit: usize) -> Vec<String> {
+            let inner = self.inner.read().await;
+
+            inner
+                .suggest(channel, input, limit)
+                .into_iter()
+                .map(String::from)
+                .collect()
+        }
+
+        /// Test if the channel already has a thing with the given pattern.
+        pub async fn contains_regex(&self, channel: &str, pattern_src: &str) -> bool {
+            self.inner.read().await.contains_regex(channel, pattern_src)
+        }
+
+        /// Test if the channel already has a thing other than the one with
+        /// the given key with the given pattern.
+        pub async fn contains_regex_except(
+            &self,
+            channel: &str,
+            key: &db::Key,
+            pattern_src: &str,
+        ) -> bool {
+            self.inner
+                .read()
+                .await
+                .contains_regex_except(channel, key, pattern_src)
+        }
+
+        /// Find the keys of every thing with a pattern which has a capture
+        /// group of the given name, in every channel.
+        pub async fn find_by_capture_name(&self, name: &str) -> Vec<db::Key> {
+            let inner = self.inner.read().await;
+            inner
+                .find_by_capture_name(name)
+                .into_iter()
+                .cloned()
+                .collect()
+        }
+
+        /// Export every thing as a `(channel, name, pattern)` row, the way it's
+        /// stored in the database.
+        ///
+        /// Things with patterns which can't be stored in the database are
+        /// skipped.
+        pub async fn to_db_rows(&self) -> Vec<(String, String, Option<String>)> {
+            self.inner.read().await.to_db_rows()
+        }
+
+        /// Find things with patterns which never match, since a thing matched
+        /// by name in the same channel takes precedence.
+        ///
+        /// Returns pairs of the key of the shadowed thing and the key of the
+        /// thing which shadows it.
+        pub async fn shadowed(&self) -> Vec<(db::Key, db::Key)> {
+            self.inner.read().await.shadowed()
+        }
+
+        /// Check that the pattern of every thing is still valid.
+        ///
+        /// Returns the keys of the things which are not, together with why.
+        pub async fn validate(&self) -> Vec<(db::Key, anyhow::Error)> {
+            self.inner.read().await.validate()
+        }
+
+        /// Set a callback to invoke with the channel and first word of inputs
+        /// which don't resolve to any thing.
+        pub async fn set_miss_handler<F>(&self, f: F)
+        where
+            F: 'static + Fn(&str, &str) + Send + Sync,
+        {
+            self.inner.write().await.set_miss_handler(f);
+        }
+
+        /// Enable or disable matching patterns in every channel, without
+        /// removing any things.
+        pub async fn set_regex_enabled(&self, enabled: bool) {
+            self.inner.write().await.set_regex_enabled(enabled);
+        }
+
+        /// Skip matching patterns in the given channel, or in every channel if
+        /// `None`, so that things are only matched by name.
+        pub async fn set_skip_regex(&self, channel: Option<&str>, skip: bool) {
+            self.inner.write().await.set_skip_regex(channel, skip);
+        }
+
+        /// Log patterns which take longer than `threshold` to match, or stop
+        /// timing patterns if `None`.
+        pub async fn set_slow_threshold(&self, threshold: Option<std::time::Duration>) {
+            self.inner.write().await.set_slow_threshold(threshold);
+        }
+
+        /// Set the strategy used to pick among matching patterns.
+        pub async fn set_resolve_strategy(&self, strategy: db::ResolveStrategy) {
+            self.inner.write().await.set_strategy(strategy);
+        }
+    };
+}
+
 /// Helper macro to build private database functions related to group management.
 macro_rules! private_database_group_fns {
     ($module:ident, $thing:ident, $key:ty) => {
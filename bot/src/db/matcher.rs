@@ -1,18 +1,80 @@
 use crate::utils;
 use anyhow::Error;
-use std::collections::{hash_map, HashMap, HashSet};
+use parking_lot::Mutex;
+use std::borrow::{Borrow, Cow};
+use std::collections::{hash_map, HashMap, HashSet, VecDeque};
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// Trait over something that has a matchable pattern.
 pub trait Matchable {
     /// Get the key for the matchable element.
     fn key(&self) -> &Key;
 
+    /// Get the key for the matchable element mutably.
+    fn key_mut(&mut self) -> &mut Key;
+
     /// Get the pattern for the matchable element.
     fn pattern(&self) -> &Pattern;
+
+    /// Get the namespace of the matchable element.
+    ///
+    /// Regular expressions in a namespace are only matched when resolving in
+    /// that namespace.
+    fn namespace(&self) -> Option<&str> {
+        None
+    }
+
+    /// Get a human-readable description of the matchable element, used when
+    /// logging resolves.
+    fn description(&self) -> Option<&str> {
+        None
+    }
+
+    /// Get the tags of the matchable element, used to restrict which elements
+    /// are considered by [resolve_filtered][Matcher::resolve_filtered].
+    fn tags(&self) -> &[String] {
+        &[]
+    }
+
+    /// Get the rate limit of the matchable element, if any.
+    ///
+    /// An element which has exceeded its rate limit is skipped when
+    /// resolving.
+    fn rate_limit(&self) -> Option<&RateLimit> {
+        None
+    }
+
+    /// Get how long to wait before responding to the matchable element once
+    /// it's resolved. `None` means responding immediately.
+    fn delay(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Test if the matchable element is enabled. Disabled elements are
+    /// skipped when resolving.
+    fn is_enabled(&self) -> bool {
+        true
+    }
+
+    /// Enable or disable the matchable element.
+    ///
+    /// Elements which can't be disabled ignore this.
+    fn set_enabled(&mut self, _enabled: bool) {}
+
+    /// Get the declared parameter names of the matchable element, used to
+    /// name its captures in [resolve_params][Matcher::resolve_params].
+    fn params(&self) -> &[String] {
+        &[]
+    }
 }
 
+/// A callback invoked with the channel and first word of inputs which didn't
+/// resolve.
+type MissHandler = Arc<dyn Fn(&str, &str) + Send + Sync>;
+
 pub struct Matcher<T>
 where
     T: Matchable,
@@ -23,6 +85,25 @@ where
     by_name: HashSet<Key>,
     /// Regular expression commands indexed by channel.
     by_channel_regex: HashMap<String, HashSet<Key>>,
+    /// Commands matched by name with arguments, indexed by the channel and
+    /// the name they match.
+    by_args: HashMap<Key, Key>,
+    /// How to pick among matching regular expressions.
+    strategy: ResolveStrategy,
+    /// Callback to invoke when an input fails to resolve.
+    miss_handler: Option<MissHandler>,
+    /// Skip matching regular expressions in every channel.
+    skip_regex_all: bool,
+    /// Channels in which matching regular expressions is skipped.
+    skip_regex: HashSet<String>,
+    /// When temporary entries expire.
+    expires: HashMap<Key, Instant>,
+    /// Entries shadowed by overrides together with when they expire, with
+    /// the most recently shadowed last. `None` if there was no entry to
+    /// shadow.
+    overrides: HashMap<Key, Vec<Option<(Arc<T>, Option<Instant>)>>>,
+    /// Log patterns which take longer than this to match, if set.
+    slow_threshold: Option<Duration>,
 }
 
 impl<T> Matcher<T>
@@ -34,43 +115,215 @@ where
             all: Default::default(),
             by_name: Default::default(),
             by_channel_regex: Default::default(),
+            by_args: Default::default(),
+            strategy: Default::default(),
+            miss_handler: None,
+            skip_regex_all: false,
+            skip_regex: Default::default(),
+            expires: Default::default(),
+            overrides: Default::default(),
+            slow_threshold: None,
+        }
+    }
+
+    /// Set the strategy used to pick among matching regular expressions.
+    pub(crate) fn set_strategy(&mut self, strategy: ResolveStrategy) {
+        self.strategy = strategy;
+    }
+
+    /// Set a callback to invoke with the channel and the first word of every
+    /// input which has a first word, but fails to [resolve][Matcher::resolve].
+    pub(crate) fn set_miss_handler<F>(&mut self, f: F)
+    where
+        F: 'static + Fn(&str, &str) + Send + Sync,
+    {
+        self.miss_handler = Some(Arc::new(f));
+    }
+
+    /// Time every regular expression matched while resolving, and log the
+    /// ones which take longer than `threshold` to match.
+    ///
+    /// Disabled with `None`, which is the default, so that nothing is timed.
+    pub(crate) fn set_slow_threshold(&mut self, threshold: Option<Duration>) {
+        self.slow_threshold = threshold;
+    }
+
+    /// Enable or disable matching regular expressions in every channel,
+    /// without removing any entries.
+    ///
+    /// Names are still matched while disabled.
+    pub(crate) fn set_regex_enabled(&mut self, enabled: bool) {
+        self.set_skip_regex(None, !enabled);
+    }
+
+    /// Skip matching regular expressions when resolving in the given channel,
+    /// or in every channel if `None`, so that only names are matched.
+    pub(crate) fn set_skip_regex(&mut self, channel: Option<&str>, skip: bool) {
+        match channel.map(normalize_channel) {
+            Some(channel) if skip => {
+                self.skip_regex.insert(channel.into_owned());
+            }
+            Some(channel) => {
+                self.skip_regex.remove(&*channel);
+            }
+            None => {
+                self.skip_regex_all = skip;
+            }
         }
     }
 
+    /// Test if matching regular expressions is skipped in the given channel.
+    fn is_regex_skipped(&self, channel: &str) -> bool {
+        self.skip_regex_all || (!self.skip_regex.is_empty() && self.skip_regex.contains(channel))
+    }
+
     /// Test if we contain the given key.
     pub(crate) fn contains_key(&self, key: &Key) -> bool {
         self.all.contains_key(key)
     }
 
-    /// Insert the given value.
-    pub(crate) fn insert(&mut self, key: Key, value: Arc<T>) {
+    /// Insert the given value, replacing any existing value with the same key.
+    ///
+    /// Names with arguments are unique per channel. If the name of the value
+    /// is already used by another entry, it takes over the name and the key
+    /// of the other entry is returned.
+    pub(crate) fn insert(&mut self, key: Key, value: Arc<T>) -> Option<Key> {
+        // NB: the existing value might have a different pattern, so it's
+        // removed from every index before the new one is added.
+        self.remove(&key);
+
+        let mut collision = None;
+
         match value.pattern() {
             Pattern::Name => {
                 self.by_name.insert(key.clone());
             }
-            Pattern::Regex { .. } => {
+            Pattern::Regex { .. } | Pattern::Keyword { .. } => {
                 self.by_channel_regex
                     .entry(key.channel.clone())
                     .or_default()
                     .insert(key.clone());
             }
+            Pattern::NameWithArgs { name } => {
+                let args_key = Key::new(&key.channel, name);
+
+                if let Some(other) = self.by_args.insert(args_key, key.clone()) {
+                    log::warn!(
+                        "{}: name `{}` was used by {}, replacing it",
+                        key,
+                        name,
+                        other
+                    );
+                    collision = Some(other);
+                }
+            }
         }
 
         self.all.insert(key, value);
+        collision
+    }
+
+    /// Insert the given value, which is treated as absent once `expires_at`
+    /// has passed and is removed by [sweep_expired][Matcher::sweep_expired].
+    pub(crate) fn insert_expiring(&mut self, key: Key, value: Arc<T>, expires_at: Instant) {
+        self.insert(key.clone(), value);
+        self.expires.insert(key, expires_at);
+    }
+
+    /// Remove every entry which has expired at `now`.
+    ///
+    /// Returns the keys of the removed entries.
+    pub(crate) fn sweep_expired(&mut self, now: Instant) -> Vec<Key> {
+        let expired = self
+            .expires
+            .iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        for key in &expired {
+            self.remove(key);
+        }
+
+        expired
+    }
+
+    /// Test if the entry with the given key has expired.
+    fn is_expired(&self, key: &dyn KeyRef) -> bool {
+        if self.expires.is_empty() {
+            return false;
+        }
+
+        match self.expires.get(key) {
+            Some(expires_at) => *expires_at <= Instant::now(),
+            None => false,
+        }
+    }
+
+    /// Insert every value which could be constructed.
+    ///
+    /// Returns the keys of the values which could not be constructed,
+    /// together with the error that prevented it, so that one bad entry
+    /// doesn't prevent the rest from loading.
+    pub(crate) fn insert_all<I, E>(&mut self, values: I) -> Vec<(Key, E)>
+    where
+        I: IntoIterator<Item = (Key, Result<T, E>)>,
+    {
+        let mut errors = Vec::new();
+
+        for (key, value) in values {
+            match value {
+                Ok(value) => {
+                    self.insert(key, Arc::new(value));
+                }
+                Err(e) => errors.push((key, e)),
+            }
+        }
+
+        errors
+    }
+
+    /// Replace every entry in the matcher with the given ones.
+    ///
+    /// The new indexes are built up separately and swapped in at the end, so
+    /// unlike clearing and re-inserting, a [resolve][Matcher::resolve]
+    /// performed through a shared lock never observes a partially loaded
+    /// matcher.
+    pub(crate) fn replace_all<I>(&mut self, entries: I)
+    where
+        I: IntoIterator<Item = (Key, Arc<T>)>,
+    {
+        let mut new = Self::new();
+        new.strategy = self.strategy;
+        new.miss_handler = self.miss_handler.clone();
+        new.skip_regex_all = self.skip_regex_all;
+        new.skip_regex = std::mem::take(&mut self.skip_regex);
+        new.slow_threshold = self.slow_threshold;
+
+        for (key, value) in entries {
+            new.insert(key, value);
+        }
+
+        *self = new;
     }
 
     /// Remove the given value.
     pub(crate) fn remove(&mut self, key: &Key) -> Option<Arc<T>> {
+        self.expires.remove(key);
+
         if let Some(value) = self.all.remove(key) {
             match value.pattern() {
                 Pattern::Name => {
                     self.by_name.remove(key);
                 }
-                Pattern::Regex { .. } => {
+                Pattern::Regex { .. } | Pattern::Keyword { .. } => {
                     self.by_channel_regex
                         .entry(key.channel.clone())
                         .or_default()
-                        .remove(&key);
+                        .remove(key);
+                }
+                Pattern::NameWithArgs { name } => {
+                    remove_args(&mut self.by_args, key, name);
                 }
             }
 
@@ -80,11 +333,251 @@ where
         None
     }
 
+    /// Retain only the entries for which `f` returns `true`, removing the rest
+    /// from every index.
+    pub(crate) fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&Key, &Arc<T>) -> bool,
+    {
+        let Self {
+            all,
+            by_name,
+            by_channel_regex,
+            by_args,
+            expires,
+            ..
+        } = self;
+
+        all.retain(|key, value| {
+            if f(key, value) {
+                return true;
+            }
+
+            expires.remove(key);
+
+            match value.pattern() {
+                Pattern::Name => {
+                    by_name.remove(key);
+                }
+                Pattern::Regex { .. } | Pattern::Keyword { .. } => {
+                    if let Some(keys) = by_channel_regex.get_mut(&key.channel) {
+                        keys.remove(key);
+                    }
+                }
+                Pattern::NameWithArgs { name } => {
+                    remove_args(by_args, key, name);
+                }
+            }
+
+            false
+        });
+    }
+
+    /// Test if the channel has a regular expression with the given source.
+    pub(crate) fn contains_regex(&self, channel: &str, pattern_src: &str) -> bool {
+        let channel = &*normalize_channel(channel);
+        self.regex_keys(channel, pattern_src).next().is_some()
+    }
+
+    /// Test if the channel has a regular expression with the given source,
+    /// ignoring the entry with the key `except`.
+    ///
+    /// This allows setting the pattern an entry already has.
+    pub(crate) fn contains_regex_except(
+        &self,
+        channel: &str,
+        except: &Key,
+        pattern_src: &str,
+    ) -> bool {
+        let channel = &*normalize_channel(channel);
+        self.regex_keys(channel, pattern_src)
+            .any(|key| key != except)
+    }
+
+    /// Iterate over the keys of the regular expressions in the channel with
+    /// the given source.
+    fn regex_keys<'a>(
+        &'a self,
+        channel: &str,
+        pattern_src: &'a str,
+    ) -> impl Iterator<Item = &'a Key> + 'a {
+        self.by_channel_regex
+            .get(channel)
+            .into_iter()
+            .flatten()
+            .filter(move |key| match self.all.get(*key) {
+                Some(value) => value
+                    .pattern()
+                    .as_regex()
+                    .map_or(false, |pattern| pattern.as_str() == pattern_src),
+                None => false,
+            })
+    }
+
+    /// Find the keys of every regular expression entry with a capture group
+    /// of the given name, in every channel.
+    pub(crate) fn find_by_capture_name(&self, name: &str) -> Vec<&Key> {
+        let mut keys = self
+            .by_channel_regex
+            .values()
+            .flatten()
+            .filter(|key| {
+                self.all
+                    .get(*key)
+                    .and_then(|value| value.pattern().as_regex())
+                    .map_or(false, |pattern| {
+                        pattern.capture_names().any(|n| n == Some(name))
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        keys.sort();
+        keys
+    }
+
+    /// Find regular expression entries which are shadowed by a name in the
+    /// same channel, returned as pairs of the regular expression key and the
+    /// key of the entry matched by name. Sorted by the regular expression
+    /// key.
+    ///
+    /// Names are matched before any regular expression, so a regular
+    /// expression never matches an input whose first word is a name. This is
+    /// a best effort diagnostic, which detects regular expressions that
+    /// match the whole name on its own, or which are anchored to start with
+    /// the name followed by whitespace.
+    pub(crate) fn shadowed(&self) -> Vec<(Key, Key)> {
+        let names = self
+            .by_name
+            .iter()
+            .map(|key| (key, key))
+            .chain(self.by_args.iter())
+            .collect::<Vec<_>>();
+
+        let mut shadowed = Vec::new();
+
+        for (channel, keys) in &self.by_channel_regex {
+            for regex_key in keys {
+                let pattern = match self.all.get(regex_key).and_then(|v| v.pattern().as_regex()) {
+                    Some(pattern) => pattern,
+                    None => continue,
+                };
+
+                // NB: anchored so that only a match of the whole name counts.
+                let whole = regex::Regex::new(&format!("^(?:{})$", pattern.as_str())).ok();
+                let leading = leading_word(pattern.as_str()).map(str::to_lowercase);
+
+                for (name, key) in &names {
+                    if name.channel != *channel {
+                        continue;
+                    }
+
+                    let is_whole = whole.as_ref().map_or(false, |w| w.is_match(&name.name));
+
+                    if is_whole || leading.as_deref() == Some(name.name.as_str()) {
+                        shadowed.push((regex_key.clone(), (*key).clone()));
+                    }
+                }
+            }
+        }
+
+        shadowed.sort();
+        shadowed
+    }
+
+    /// Export every entry as a `(channel, name, pattern)` row, the way it's
+    /// stored in the database. See [Pattern::to_db].
+    ///
+    /// Entries with patterns which can't be stored in the database are
+    /// skipped. Rows are sorted by channel and name.
+    pub(crate) fn to_db_rows(&self) -> Vec<(String, String, Option<String>)> {
+        let mut rows = self
+            .all
+            .iter()
+            .filter_map(|(key, value)| {
+                let pattern = value.pattern().to_db()?;
+                Some((
+                    key.channel.clone(),
+                    key.name.clone(),
+                    pattern.map(String::from),
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        rows.sort();
+        rows
+    }
+
+    /// Check that every regular expression entry still compiles from its
+    /// source, within the default size limit, with the same number of
+    /// capture groups.
+    ///
+    /// Returns the keys of the entries which failed, together with why.
+    pub(crate) fn validate(&self) -> Vec<(Key, Error)> {
+        let mut errors = Vec::new();
+
+        for (key, value) in self.iter_sorted() {
+            let pattern = match value.pattern().as_regex() {
+                Some(pattern) => pattern,
+                None => continue,
+            };
+
+            let compiled = match Pattern::compile(pattern.as_str()) {
+                Ok(compiled) => compiled,
+                Err(e) => {
+                    errors.push((key.clone(), Error::from(e)));
+                    continue;
+                }
+            };
+
+            if compiled.captures_len() != pattern.captures_len() {
+                errors.push((
+                    key.clone(),
+                    anyhow::anyhow!(
+                        "expected {} capture groups, but recompiled pattern has {}",
+                        pattern.captures_len(),
+                        compiled.captures_len()
+                    ),
+                ));
+            }
+        }
+
+        errors
+    }
+
+    /// Get up to `limit` names in the channel which are closest to `input`
+    /// by edit distance, closest first.
+    pub(crate) fn suggest(&self, channel: &str, input: &str, limit: usize) -> Vec<&str> {
+        let channel = &*normalize_channel(channel);
+
+        let input = input.to_lowercase();
+
+        let mut names = self
+            .by_name
+            .iter()
+            .filter(|key| key.channel == channel)
+            .map(|key| (edit_distance(&input, &key.name), key.name.as_str()))
+            .collect::<Vec<_>>();
+
+        names.sort();
+        names
+            .into_iter()
+            .take(limit)
+            .map(|(_, name)| name)
+            .collect()
+    }
+
     /// Get an iterator over all the values.
     pub(crate) fn iter(&self) -> hash_map::Iter<'_, Key, Arc<T>> {
         self.all.iter()
     }
 
+    /// Get all entries sorted by channel and name.
+    pub(crate) fn iter_sorted(&self) -> Vec<(&Key, &Arc<T>)> {
+        let mut entries = self.all.iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        entries
+    }
+
     /// Get an iterator over all the values.
     pub(crate) fn values(&self) -> hash_map::Values<'_, Key, Arc<T>> {
         self.all.values()
@@ -95,8 +588,46 @@ where
         self.all.get(key)
     }
 
+    /// Get the value with the given key, together with which index it
+    /// resides in.
+    ///
+    /// The kind is taken from the index the key is actually a member of,
+    /// and an entry which isn't indexed according to its pattern is treated
+    /// as absent.
+    pub(crate) fn get_with_kind(&self, key: &Key) -> Option<(&Arc<T>, MatchKind)> {
+        let value = self.all.get(key)?;
+
+        let in_regex = self
+            .by_channel_regex
+            .get(&key.channel)
+            .map_or(false, |keys| keys.contains(key));
+
+        let kind = match (value.pattern(), self.by_name.contains(key), in_regex) {
+            (Pattern::Name, true, false) => MatchKind::Name,
+            (Pattern::Regex { .. }, false, true) => MatchKind::Regex,
+            (Pattern::Keyword { .. }, false, true) => MatchKind::Keyword,
+            (Pattern::NameWithArgs { name }, false, false)
+                if self.by_args.get(&Key::new(&key.channel, name)) == Some(key) =>
+            {
+                MatchKind::NameWithArgs
+            }
+            _ => {
+                log::error!("entry `{}` is not indexed according to its pattern", key);
+                return None;
+            }
+        };
+
+        Some((value, kind))
+    }
+
     /// Modify the given element with the given pattern.
     /// Returns `true` if there was a value to modify. `false` otherwise.
+    ///
+    /// The modified value is fully constructed before any index is touched,
+    /// and since this requires exclusive access to the matcher, a concurrent
+    /// [resolve][Matcher::resolve] performed through a shared lock will
+    /// either observe the old entry with its old index, or the new entry
+    /// with its new index. Never a mix of the two.
     pub(crate) fn modify<F>(&mut self, key: Key, m: F) -> bool
     where
         T: Clone,
@@ -106,6 +637,8 @@ where
             all,
             by_channel_regex,
             by_name,
+            by_args,
+            ..
         } = self;
 
         let existing = match all.get_mut(&key) {
@@ -117,26 +650,33 @@ where
         m(&mut new);
 
         // re-index in case pattern has changed.
-        match new.pattern() {
-            Pattern::Regex { .. } => {
-                if let Pattern::Name = existing.pattern() {
-                    by_name.remove(&key);
-
-                    by_channel_regex
-                        .entry(key.channel.clone())
-                        .or_default()
-                        .insert(key);
-                }
-            }
+        match existing.pattern() {
             Pattern::Name => {
-                if let Pattern::Regex { .. } = existing.pattern() {
-                    by_channel_regex
-                        .entry(key.channel.clone())
-                        .or_default()
-                        .remove(&key);
+                by_name.remove(&key);
+            }
+            Pattern::Regex { .. } | Pattern::Keyword { .. } => {
+                by_channel_regex
+                    .entry(key.channel.clone())
+                    .or_default()
+                    .remove(&key);
+            }
+            Pattern::NameWithArgs { name } => {
+                remove_args(by_args, &key, name);
+            }
+        }
 
-                    by_name.insert(key);
-                }
+        match new.pattern() {
+            Pattern::Name => {
+                by_name.insert(key);
+            }
+            Pattern::Regex { .. } | Pattern::Keyword { .. } => {
+                by_channel_regex
+                    .entry(key.channel.clone())
+                    .or_default()
+                    .insert(key);
+            }
+            Pattern::NameWithArgs { name } => {
+                by_args.insert(Key::new(&key.channel, name), key);
             }
         }
 
@@ -144,153 +684,2689 @@ where
         true
     }
 
-    /// Resolve the given command.
-    pub fn resolve<'a>(
-        &self,
-        channel: &str,
-        first: Option<&'a str>,
-        it: &'a utils::Words,
-    ) -> Option<(&Arc<T>, Captures<'a>)> {
-        if let Some(first) = first {
-            let key = Key::new(channel, first);
+    /// Shadow the entry with the given key with an override, which is
+    /// resolved in its place until it's [popped][Matcher::pop_override].
+    ///
+    /// Overrides can be stacked, and the override is indexed according to
+    /// its own pattern.
+    pub(crate) fn push_override(&mut self, key: Key, value: Arc<T>) {
+        let expires_at = self.expires.get(&key).copied();
+        let shadowed = self.remove(&key).map(|value| (value, expires_at));
+        self.overrides
+            .entry(key.clone())
+            .or_default()
+            .push(shadowed);
+        self.insert(key, value);
+    }
 
-            if self.by_name.contains(&key) {
-                if let Some(command) = self.get(&key) {
-                    let captures = Captures::Prefix { rest: it.rest() };
-                    return Some((command, captures));
-                }
-            }
+    /// Remove the most recent override for the given key, restoring the
+    /// entry it shadowed.
+    ///
+    /// Returns the removed override, or `None` if there is no override.
+    pub(crate) fn pop_override(&mut self, key: &Key) -> Option<Arc<T>> {
+        let stack = self.overrides.get_mut(key)?;
+        let shadowed = stack.pop()?;
+
+        if stack.is_empty() {
+            self.overrides.remove(key);
         }
 
-        if let Some(keys) = self.by_channel_regex.get(channel) {
-            let full = it.string();
+        let value = self.remove(key);
 
-            for key in keys {
-                if let Some(command) = self.get(key) {
-                    if let Pattern::Regex { pattern } = command.pattern() {
-                        if let Some(captures) = pattern.captures(full) {
-                            let captures = Captures::Regex { captures };
-                            return Some((command, captures));
-                        }
-                    }
-                }
+        match shadowed {
+            Some((shadowed, Some(expires_at))) => {
+                self.insert_expiring(key.clone(), shadowed, expires_at);
+            }
+            Some((shadowed, None)) => {
+                self.insert(key.clone(), shadowed);
             }
+            None => (),
         }
 
-        None
+        value
     }
-}
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize)]
-pub struct Key {
-    pub channel: String,
-    pub name: String,
-}
+    /// Enable or disable every entry in the given channel.
+    ///
+    /// Returns the number of entries whose enabled state changed.
+    pub(crate) fn set_channel_enabled(&mut self, channel: &str, enabled: bool) -> usize
+    where
+        T: Clone,
+    {
+        let channel = &*normalize_channel(channel);
 
-impl Key {
-    pub fn new(channel: &str, name: &str) -> Self {
-        Self {
-            channel: channel.to_string(),
-            name: name.to_lowercase(),
+        let mut changed = 0;
+
+        for (key, value) in &mut self.all {
+            if key.channel != channel || value.is_enabled() == enabled {
+                continue;
+            }
+
+            let mut new = (**value).clone();
+            new.set_enabled(enabled);
+            *value = Arc::new(new);
+            changed += 1;
         }
-    }
-}
 
-impl fmt::Display for Key {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(fmt, "{}/{}", self.channel, self.name)
+        changed
     }
-}
 
-/// How to match the given value.
-#[derive(Debug, Clone, serde::Serialize)]
-#[serde(tag = "type")]
-pub enum Pattern {
-    #[serde(rename = "name")]
-    Name,
-    #[serde(rename = "regex")]
-    Regex {
-        #[serde(serialize_with = "serialize_regex")]
-        pattern: regex::Regex,
-    },
-}
+    /// Move every entry in channel `from` to channel `to`.
+    ///
+    /// Returns the keys of the entries moved. With [Collision::Error],
+    /// nothing is moved if any entry would collide with an existing one in
+    /// `to`.
+    pub(crate) fn migrate_channel(
+        &mut self,
+        from: &str,
+        to: &str,
+        collision: Collision,
+    ) -> Result<Vec<(Key, Key)>, super::RenameError>
+    where
+        T: Clone,
+    {
+        let from = &*normalize_channel(from);
+        let to = &*normalize_channel(to);
 
-impl Pattern {
-    /// Construct a new pattern from a regular expression.
-    pub fn regex(pattern: regex::Regex) -> Self {
-        Self::Regex { pattern }
-    }
+        if from == to {
+            return Ok(Vec::new());
+        }
 
-    /// Convert a database pattern into a matchable pattern here.
-    pub fn from_db(pattern: Option<impl AsRef<str>>) -> Result<Self, Error> {
-        Ok(match pattern {
-            Some(pattern) => Pattern::Regex {
-                pattern: regex::Regex::new(pattern.as_ref())?,
-            },
-            None => Pattern::Name,
-        })
-    }
-}
+        let mut moves = Vec::new();
 
-impl Default for Pattern {
-    fn default() -> Self {
-        Self::Name
-    }
-}
+        for key in self.all.keys().filter(|key| key.channel == from) {
+            let to_key = Key {
+                channel: to.to_string(),
+                name: key.name.clone(),
+            };
 
-impl fmt::Display for Pattern {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Pattern::Name => "*name*".fmt(fmt),
-            Pattern::Regex { pattern } => pattern.fmt(fmt),
+            if self.all.contains_key(&to_key) {
+                match collision {
+                    Collision::Error => return Err(super::RenameError::Conflict),
+                    Collision::Skip => continue,
+                }
+            }
+
+            moves.push((key.clone(), to_key));
+        }
+
+        for (from_key, to_key) in &moves {
+            self.move_entry(from_key, to_key.clone());
         }
+
+        Ok(moves)
     }
-}
 
-/// Serialize a regular expression.
-fn serialize_regex<S>(regex: &regex::Regex, s: S) -> Result<S::Ok, S::Error>
-where
-    S: serde::Serializer,
-{
-    s.collect_str(regex)
-}
-
-#[derive(Debug)]
-pub enum Captures<'a> {
-    Prefix { rest: &'a str },
-    Regex { captures: regex::Captures<'a> },
-}
+    /// Rename the entry with key `from` to `to`.
+    ///
+    /// An expiring entry keeps expiring at the same time.
+    pub(crate) fn rename(&mut self, from: &Key, to: Key) -> Result<(), super::RenameError>
+    where
+        T: Clone,
+    {
+        if self.all.contains_key(&to) {
+            return Err(super::RenameError::Conflict);
+        }
 
-impl<'a> Captures<'a> {
-    /// Get the number of captures.
-    fn len(&self) -> usize {
-        match self {
-            Self::Prefix { .. } => 1,
-            Self::Regex { captures, .. } => captures.len(),
+        if !self.move_entry(from, to) {
+            return Err(super::RenameError::Missing);
         }
+
+        Ok(())
     }
-}
 
-impl serde::Serialize for Captures<'_> {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    /// Move the entry with key `from` to `to`, together with when it expires.
+    ///
+    /// Returns `false` if there is no such entry.
+    fn move_entry(&mut self, from: &Key, to: Key) -> bool
     where
-        S: serde::Serializer,
+        T: Clone,
     {
-        use serde::ser::SerializeMap as _;
+        let expires_at = self.expires.get(from).copied();
 
-        let mut m = serializer.serialize_map(Some(self.len()))?;
+        let value = match self.remove(from) {
+            Some(value) => value,
+            None => return false,
+        };
 
-        match self {
-            Self::Prefix { rest } => {
-                m.serialize_entry("rest", rest)?;
+        let mut value = (*value).clone();
+        *value.key_mut() = to.clone();
+        let value = Arc::new(value);
+
+        match expires_at {
+            Some(expires_at) => self.insert_expiring(to, value, expires_at),
+            None => {
+                self.insert(to, value);
             }
-            Self::Regex { captures, .. } => {
-                for (i, g) in captures.iter().enumerate() {
-                    m.serialize_entry(&i, &g.map(|m| m.as_str()))?;
+        }
+
+        true
+    }
+
+    /// Resolve the given command.
+    pub fn resolve<'a>(
+        &self,
+        channel: &str,
+        first: Option<&'a str>,
+        it: &'a utils::Words,
+    ) -> Option<(&Arc<T>, Captures<'a>)> {
+        let channel = &*normalize_channel(channel);
+
+        let resolved = self.resolve_with(channel, first, it, true, None);
+
+        if let (None, Some(first), Some(miss_handler)) = (&resolved, first, &self.miss_handler) {
+            miss_handler(channel, first);
+        }
+
+        resolved
+    }
+
+    /// Resolve the given command like [resolve][Matcher::resolve], but fail
+    /// if the input matches more than one pattern.
+    ///
+    /// Names are unique and always take precedence, so only patterns can be
+    /// ambiguous. This matches every pattern in the channel, which makes it
+    /// more expensive than [resolve][Matcher::resolve].
+    pub fn resolve_checked<'a>(
+        &self,
+        channel: &str,
+        first: Option<&'a str>,
+        it: &'a utils::Words,
+    ) -> Result<Option<(&Arc<T>, Captures<'a>)>, ResolveError> {
+        let channel = &*normalize_channel(channel);
+
+        if let Some(first) = first {
+            let name = first.to_lowercase();
+
+            if let Some(resolved) = self.resolve_name(channel, &name, it, true, None) {
+                return Ok(Some(resolved));
+            }
+        }
+
+        let keys = match self.by_channel_regex.get(channel) {
+            Some(keys) if !self.is_regex_skipped(channel) => keys,
+            _ => return Ok(None),
+        };
+
+        let full = it.string();
+
+        let mut candidates = keys
+            .iter()
+            .filter_map(|key| self.candidate(key, None, full, full, None))
+            .collect::<Vec<_>>();
+
+        if candidates.len() > 1 {
+            let mut keys = candidates
+                .into_iter()
+                .map(|(command, _)| command.key().clone())
+                .collect::<Vec<_>>();
+
+            keys.sort();
+            return Err(ResolveError::Ambiguous(keys));
+        }
+
+        let (command, captures) = match candidates.pop() {
+            Some(candidate) => candidate,
+            None => return Ok(None),
+        };
+
+        resolved(&**command, true);
+        Ok(Some((command, captures)))
+    }
+
+    /// Resolve the given command, returning owned results which don't borrow
+    /// from the matcher or the input.
+    ///
+    /// This allows the lock guarding the matcher to be released before the
+    /// resolved command is processed.
+    pub fn resolve_owned(
+        &self,
+        channel: &str,
+        first: Option<&str>,
+        it: &utils::Words,
+    ) -> Option<(Arc<T>, OwnedCaptures)> {
+        let (command, captures) = self.resolve(channel, first, it)?;
+        let captures = OwnedCaptures::new(command.pattern(), &captures);
+        Some((command.clone(), captures))
+    }
+
+    /// Resolve the given command like [resolve][Matcher::resolve], but return
+    /// the captures keyed by the parameter names declared by the resolved
+    /// element.
+    ///
+    /// See [Captures::params] for how captures are named.
+    pub fn resolve_params<'a>(
+        &self,
+        channel: &str,
+        first: Option<&'a str>,
+        it: &'a utils::Words,
+    ) -> Option<(&Arc<T>, HashMap<String, &'a str>)> {
+        let (command, captures) = self.resolve(channel, first, it)?;
+        let params = captures.params(command.params());
+        Some((command, params))
+    }
+
+    /// Resolve the given command, only considering entries whose tags
+    /// intersect with `allowed_tags`.
+    ///
+    /// This allows restricting which commands are available without
+    /// removing any entries.
+    pub fn resolve_filtered<'a>(
+        &self,
+        channel: &str,
+        first: Option<&'a str>,
+        it: &'a utils::Words,
+        allowed_tags: &HashSet<String>,
+    ) -> Option<(&Arc<T>, Captures<'a>)> {
+        let channel = &*normalize_channel(channel);
+
+        self.resolve_with(channel, first, it, true, Some(allowed_tags))
+    }
+
+    /// Resolve the given command, only counting it towards its rate limit if
+    /// `hit` is set, and only considering entries with an allowed tag if
+    /// `allowed` is specified.
+    fn resolve_with<'a>(
+        &self,
+        channel: &str,
+        first: Option<&'a str>,
+        it: &'a utils::Words,
+        hit: bool,
+        allowed: Option<&HashSet<String>>,
+    ) -> Option<(&Arc<T>, Captures<'a>)> {
+        if let Some(first) = first {
+            // NB: avoid allocating a lowercased copy in the common case where
+            // the input already is lowercase.
+            let name = if first.chars().all(|c| c.to_lowercase().eq(Some(c))) {
+                Cow::Borrowed(first)
+            } else {
+                Cow::Owned(first.to_lowercase())
+            };
+
+            if let Some(resolved) = self.resolve_name(channel, &name, it, hit, allowed) {
+                return Some(resolved);
+            }
+        }
+
+        self.resolve_in_namespace_with(channel, None, it.string(), it.string(), hit, allowed)
+    }
+
+    /// Resolve the given command, trusting that `first` is already
+    /// lowercased.
+    ///
+    /// This never allocates when looking up names, but an input which is not
+    /// lowercase will not match any name.
+    pub fn resolve_lower<'a>(
+        &self,
+        channel: &str,
+        first: Option<&'a str>,
+        it: &'a utils::Words,
+    ) -> Option<(&Arc<T>, Captures<'a>)> {
+        let channel = &*normalize_channel(channel);
+
+        if let Some(first) = first {
+            if let Some(resolved) = self.resolve_name(channel, first, it, true, None) {
+                return Some(resolved);
+            }
+        }
+
+        self.resolve_in_namespace_with(channel, None, it.string(), it.string(), true, None)
+    }
+
+    /// Resolve an entry matched by its lowercased name.
+    fn resolve_name<'a>(
+        &self,
+        channel: &str,
+        name: &str,
+        it: &'a utils::Words,
+        hit: bool,
+        allowed: Option<&HashSet<String>>,
+    ) -> Option<(&Arc<T>, Captures<'a>)> {
+        let key = &(channel, name) as &dyn KeyRef;
+
+        let (key, captures) = if self.by_name.contains(key) {
+            (key, Captures::Prefix { rest: it.rest() })
+        } else {
+            let key = self.by_args.get(key)?;
+
+            let captures = Captures::Args {
+                full: it.string(),
+                rest: it.rest(),
+            };
+
+            (key as &dyn KeyRef, captures)
+        };
+
+        let command = self.all.get(key)?;
+
+        if is_rate_limited(&**command) || self.is_expired(key) || !is_allowed(&**command, allowed) {
+            return None;
+        }
+
+        resolved(&**command, hit);
+        Some((command, captures))
+    }
+
+    /// Resolve the given command, also matching the first word if it's a
+    /// unique prefix of a name in the channel.
+    ///
+    /// An ambiguous prefix doesn't match any name. This scans every name, so
+    /// prefer [resolve][Matcher::resolve] unless prefix matching is wanted.
+    pub fn resolve_prefix<'a>(
+        &self,
+        channel: &str,
+        first: Option<&'a str>,
+        it: &'a utils::Words,
+    ) -> Option<(&Arc<T>, Captures<'a>)> {
+        let channel = &*normalize_channel(channel);
+
+        if let Some(first) = first {
+            let prefix = first.to_lowercase();
+
+            if !prefix.is_empty() && !self.by_name.contains(&Key::new(channel, &prefix)) {
+                let mut matches = self
+                    .by_name
+                    .iter()
+                    .filter(|key| key.channel == channel && key.name.starts_with(&prefix));
+
+                if let (Some(key), None) = (matches.next(), matches.next()) {
+                    let command = self.get(key).filter(|c| {
+                        c.is_enabled() && !is_rate_limited(&***c) && !self.is_expired(key)
+                    });
+
+                    if let Some(command) = command {
+                        resolved(&**command, true);
+                        let captures = Captures::Prefix { rest: it.rest() };
+                        return Some((command, captures));
+                    }
                 }
             }
         }
 
-        m.end()
+        self.resolve(channel, first, it)
+    }
+
+    /// Explain what the given input would resolve to, without acting on it.
+    pub fn explain<'a>(
+        &self,
+        channel: &str,
+        first: Option<&'a str>,
+        it: &'a utils::Words,
+    ) -> Explanation<'a> {
+        let channel = &*normalize_channel(channel);
+
+        match self.resolve_with(channel, first, it, false, None) {
+            Some((value, captures)) => Explanation {
+                key: Some(value.key().clone()),
+                pattern: Some(value.pattern().clone()),
+                captures: Some(captures),
+            },
+            None => Explanation::default(),
+        }
+    }
+
+    /// Resolve the given input against regular expressions in the given
+    /// namespace only.
+    pub fn resolve_in_namespace<'a>(
+        &self,
+        channel: &str,
+        namespace: Option<&str>,
+        full: &'a str,
+    ) -> Option<(&Arc<T>, Captures<'a>)> {
+        let channel = &*normalize_channel(channel);
+
+        self.resolve_in_namespace_with(channel, namespace, full, full, true, None)
+    }
+
+    /// Resolve the given command, matching regular expressions against a
+    /// cleaned up version of the input, like one with emotes removed.
+    ///
+    /// Names are still matched against `first`. Captures are taken from the
+    /// full input in `it` when the pattern matches it as well, so that they
+    /// line up with the original message. Otherwise they are taken from
+    /// `clean`.
+    pub fn resolve_with_clean<'a>(
+        &self,
+        channel: &str,
+        first: Option<&'a str>,
+        clean: &'a str,
+        it: &'a utils::Words,
+    ) -> Option<(&Arc<T>, Captures<'a>)> {
+        let channel = &*normalize_channel(channel);
+
+        if let Some(first) = first {
+            if let Some(resolved) =
+                self.resolve_name(channel, &first.to_lowercase(), it, true, None)
+            {
+                return Some(resolved);
+            }
+        }
+
+        self.resolve_in_namespace_with(channel, None, clean, it.string(), true, None)
+    }
+
+    /// Resolve the given input against regular expressions in the given
+    /// namespace, only counting it towards its rate limit if `hit` is set.
+    ///
+    /// Patterns are matched against `clean`, but captures are taken from
+    /// `full` if possible.
+    fn resolve_in_namespace_with<'a>(
+        &self,
+        channel: &str,
+        namespace: Option<&str>,
+        clean: &'a str,
+        full: &'a str,
+        hit: bool,
+        allowed: Option<&HashSet<String>>,
+    ) -> Option<(&Arc<T>, Captures<'a>)> {
+        if self.is_regex_skipped(channel) {
+            return None;
+        }
+
+        let keys = self.by_channel_regex.get(channel)?;
+
+        let mut candidates = keys
+            .iter()
+            .filter_map(|key| self.candidate(key, namespace, clean, full, allowed));
+
+        let (command, captures) = match self.strategy {
+            ResolveStrategy::First => candidates.next()?,
+            ResolveStrategy::LongestMatch => {
+                candidates.max_by_key(|(_, captures)| captures.get(0).map(str::len))?
+            }
+        };
+
+        resolved(&**command, hit);
+        Some((command, captures))
+    }
+
+    /// Match the pattern of the entry with the given key, if it is a
+    /// candidate when resolving in the given namespace.
+    fn candidate<'a>(
+        &self,
+        key: &Key,
+        namespace: Option<&str>,
+        clean: &'a str,
+        full: &'a str,
+        allowed: Option<&HashSet<String>>,
+    ) -> Option<(&Arc<T>, Captures<'a>)> {
+        let command = self.get(key)?;
+
+        if command.namespace() != namespace
+            || is_rate_limited(&**command)
+            || self.is_expired(key)
+            || !is_allowed(&**command, allowed)
+        {
+            return None;
+        }
+
+        match command.pattern() {
+            Pattern::Regex { pattern } => {
+                let captures = self.captures(key, pattern, clean)?;
+
+                if std::ptr::eq(clean, full) {
+                    return Some((command, Captures::Regex { captures }));
+                }
+
+                let full = self.captures(key, pattern, full);
+                let captures = full.unwrap_or(captures);
+                Some((command, Captures::Regex { captures }))
+            }
+            Pattern::Keyword { word } => {
+                let matched = find_keyword(clean, word)?;
+
+                let matched = if std::ptr::eq(clean, full) {
+                    matched
+                } else {
+                    find_keyword(full, word).unwrap_or(matched)
+                };
+
+                Some((command, Captures::Keyword { word: matched }))
+            }
+            Pattern::Name | Pattern::NameWithArgs { .. } => None,
+        }
+    }
+
+    /// Match the pattern of the entry with the given key against `input`,
+    /// timing it if a [slow threshold][Matcher::set_slow_threshold] is set.
+    fn captures<'a>(
+        &self,
+        key: &Key,
+        pattern: &regex::Regex,
+        input: &'a str,
+    ) -> Option<regex::Captures<'a>> {
+        let threshold = match self.slow_threshold {
+            Some(threshold) => threshold,
+            None => return pattern.captures(input),
+        };
+
+        let start = Instant::now();
+        let captures = pattern.captures(input);
+        let elapsed = start.elapsed();
+
+        if elapsed > threshold {
+            log::warn!(
+                "slow pattern in {}: `{}` took {:?} to match {} bytes",
+                key,
+                pattern,
+                elapsed,
+                input.len()
+            );
+        }
+
+        captures
+    }
+}
+
+/// Compute the Levenshtein distance between two strings, by characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, b) in b.iter().enumerate() {
+            let cost = if a == *b { diagonal } else { diagonal + 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = usize::min(cost, usize::min(row[j], row[j + 1]) + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Test if the given element is enabled and has one of the allowed tags, if
+/// any are specified.
+fn is_allowed<T>(value: &T, allowed: Option<&HashSet<String>>) -> bool
+where
+    T: Matchable,
+{
+    if !value.is_enabled() {
+        return false;
+    }
+
+    match allowed {
+        Some(allowed) => value.tags().iter().any(|tag| allowed.contains(tag)),
+        None => true,
+    }
+}
+
+/// Remove the entry with the given key from the index of names with
+/// arguments, unless another entry has since taken its name.
+fn remove_args(by_args: &mut HashMap<Key, Key>, key: &Key, name: &str) {
+    let args_key = Key::new(&key.channel, name);
+
+    if by_args.get(&args_key) == Some(key) {
+        by_args.remove(&args_key);
+    }
+}
+
+/// Get the literal word which a regular expression requires its input to
+/// start with, if it's anchored to the start of the input and the word is
+/// followed by whitespace.
+fn leading_word(source: &str) -> Option<&str> {
+    let rest = source.strip_prefix('^')?;
+
+    let end = rest
+        .find(|c: char| c.is_whitespace() || "\\.+*?()|[]{}^$".contains(c))
+        .unwrap_or(rest.len());
+
+    let (word, tail) = rest.split_at(end);
+
+    if word.is_empty() || !(tail.starts_with(' ') || tail.starts_with("\\s")) {
+        return None;
+    }
+
+    Some(word)
+}
+
+/// Test if the given element has exceeded its rate limit.
+fn is_rate_limited<T>(value: &T) -> bool
+where
+    T: Matchable,
+{
+    value.rate_limit().map_or(false, RateLimit::is_exceeded)
+}
+
+/// Log that the given element was resolved, and count it towards its rate
+/// limit if `hit` is set.
+fn resolved<T>(value: &T, hit: bool)
+where
+    T: Matchable,
+{
+    if !hit {
+        return;
+    }
+
+    if let Some(rate_limit) = value.rate_limit() {
+        rate_limit.hit();
+    }
+
+    log::debug!(
+        "resolved {}: {}",
+        value.key(),
+        value.description().unwrap_or("*no description*")
+    );
+}
+
+/// A limit on how many times an element can be resolved within a rolling
+/// window.
+#[derive(Debug)]
+pub struct RateLimit {
+    count: usize,
+    window: Duration,
+    /// When the element was resolved within the current window.
+    hits: Mutex<VecDeque<Instant>>,
+}
+
+impl RateLimit {
+    /// Allow at most `count` resolves within any `window`.
+    pub fn new(count: usize, window: Duration) -> Self {
+        Self {
+            count,
+            window,
+            hits: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Test if the limit has been reached.
+    fn is_exceeded(&self) -> bool {
+        let now = Instant::now();
+        let mut hits = self.hits.lock();
+
+        while let Some(hit) = hits.front() {
+            if now.saturating_duration_since(*hit) < self.window {
+                break;
+            }
+
+            hits.pop_front();
+        }
+
+        hits.len() >= self.count
+    }
+
+    /// Record a resolve.
+    fn hit(&self) {
+        self.hits.lock().push_back(Instant::now());
+    }
+}
+
+impl Clone for RateLimit {
+    fn clone(&self) -> Self {
+        Self {
+            count: self.count,
+            window: self.window,
+            hits: Mutex::new(self.hits.lock().clone()),
+        }
+    }
+}
+
+/// What an input would resolve to, as returned by [Matcher::explain].
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Explanation<'a> {
+    /// The key of the matched element, if any.
+    pub key: Option<Key>,
+    /// The pattern of the matched element.
+    pub pattern: Option<Pattern>,
+    /// The captures produced by the match.
+    pub captures: Option<Captures<'a>>,
+}
+
+/// Which index an entry resides in, as returned by
+/// [get_with_kind][Matcher::get_with_kind].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchKind {
+    /// Matched by name.
+    Name,
+    /// Matched by regular expression.
+    Regex,
+    /// Matched by keyword.
+    Keyword,
+    /// Matched by name, with arguments.
+    NameWithArgs,
+}
+
+/// How to pick among several matching regular expressions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolveStrategy {
+    /// Pick the first pattern which matches.
+    First,
+    /// Pick the pattern with the longest matched span, so that more specific
+    /// patterns win over broad ones.
+    LongestMatch,
+}
+
+impl Default for ResolveStrategy {
+    fn default() -> Self {
+        Self::First
+    }
+}
+
+/// Errors raised by [resolve_checked][Matcher::resolve_checked].
+#[derive(Debug, thiserror::Error)]
+pub enum ResolveError {
+    /// The input matched the patterns of more than one entry.
+    #[error("input matched more than one pattern: {}", display_keys(.0))]
+    Ambiguous(Vec<Key>),
+}
+
+/// Display a list of keys, separated by commas.
+fn display_keys(keys: &[Key]) -> String {
+    keys.iter()
+        .map(Key::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// How to handle key collisions when moving entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collision {
+    /// Fail the whole operation.
+    Error,
+    /// Leave the colliding entry where it is.
+    Skip,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize)]
+pub struct Key {
+    pub channel: String,
+    pub name: String,
+}
+
+impl Key {
+    /// Construct a new key.
+    ///
+    /// The name is lowercased, and the channel is lowercased and given a
+    /// leading `#` if it's missing one, so that `#Foo` and `foo` refer to the
+    /// same channel.
+    pub fn new(channel: &str, name: &str) -> Self {
+        Self {
+            channel: normalize_channel(channel).into_owned(),
+            name: name.to_lowercase(),
+        }
+    }
+}
+
+/// Normalize the name of a channel the same way [Key::new] does.
+///
+/// Doesn't allocate if the channel is already normalized.
+pub(crate) fn normalize_channel(channel: &str) -> Cow<'_, str> {
+    if channel.starts_with('#') && channel.chars().all(|c| c.to_lowercase().eq(Some(c))) {
+        return Cow::Borrowed(channel);
+    }
+
+    let channel = channel.strip_prefix('#').unwrap_or(channel);
+    Cow::Owned(format!("#{}", channel.to_lowercase()))
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{}/{}", self.channel, self.name)
+    }
+}
+
+/// A borrowed view of a key, used to look up keys without allocating.
+pub trait KeyRef {
+    /// The channel of the key.
+    fn channel(&self) -> &str;
+
+    /// The name of the key.
+    fn name(&self) -> &str;
+}
+
+impl KeyRef for Key {
+    fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl KeyRef for (&str, &str) {
+    fn channel(&self) -> &str {
+        self.0
+    }
+
+    fn name(&self) -> &str {
+        self.1
+    }
+}
+
+impl<'a> Borrow<dyn KeyRef + 'a> for Key {
+    fn borrow(&self) -> &(dyn KeyRef + 'a) {
+        self
+    }
+}
+
+/// NB: must hash the same way as the derived implementation for [Key].
+impl Hash for dyn KeyRef + '_ {
+    fn hash<H>(&self, state: &mut H)
+    where
+        H: Hasher,
+    {
+        self.channel().hash(state);
+        self.name().hash(state);
+    }
+}
+
+impl PartialEq for dyn KeyRef + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.channel() == other.channel() && self.name() == other.name()
+    }
+}
+
+impl Eq for dyn KeyRef + '_ {}
+
+/// How to match the given value.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "type")]
+pub enum Pattern {
+    #[serde(rename = "name")]
+    Name,
+    #[serde(rename = "regex")]
+    Regex {
+        #[serde(serialize_with = "serialize_regex")]
+        pattern: regex::Regex,
+    },
+    /// Matches a single word anywhere in the input, on word boundaries and
+    /// ignoring case.
+    #[serde(rename = "keyword")]
+    Keyword { word: String },
+    /// Matches the first word of the input as a name, capturing the rest of
+    /// the input as its arguments.
+    #[serde(rename = "name-with-args")]
+    NameWithArgs { name: String },
+}
+
+impl Pattern {
+    /// Construct a new pattern from a regular expression.
+    pub fn regex(pattern: regex::Regex) -> Self {
+        Self::Regex { pattern }
+    }
+
+    /// Construct a new pattern matching the given keyword.
+    pub fn keyword(word: &str) -> Self {
+        Self::Keyword {
+            word: word.to_lowercase(),
+        }
+    }
+
+    /// Get the regular expression of the pattern, if it is one.
+    pub fn as_regex(&self) -> Option<&regex::Regex> {
+        match self {
+            Self::Regex { pattern } => Some(pattern),
+            Self::Name | Self::Keyword { .. } | Self::NameWithArgs { .. } => None,
+        }
+    }
+
+    /// Compile a regular expression for use in a pattern.
+    ///
+    /// Patterns are case-insensitive by default, which can be disabled with
+    /// an inline `(?-i)` flag.
+    pub fn compile(pattern: &str) -> Result<regex::Regex, regex::Error> {
+        regex::RegexBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+    }
+
+    /// Convert a database pattern into a matchable pattern here.
+    pub fn from_db(pattern: Option<impl AsRef<str>>) -> Result<Self, Error> {
+        Ok(match pattern {
+            Some(pattern) => Pattern::Regex {
+                pattern: Self::compile(pattern.as_ref())?,
+            },
+            None => Pattern::Name,
+        })
+    }
+
+    /// Convert the pattern into its database representation, the inverse of
+    /// [from_db][Pattern::from_db].
+    ///
+    /// Returns `None` for keyword patterns and names with arguments, which
+    /// can't be stored in the database.
+    pub fn to_db(&self) -> Option<Option<&str>> {
+        match self {
+            Self::Name => Some(None),
+            Self::Regex { pattern } => Some(Some(pattern.as_str())),
+            Self::Keyword { .. } | Self::NameWithArgs { .. } => None,
+        }
+    }
+}
+
+impl Default for Pattern {
+    fn default() -> Self {
+        Self::Name
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Name => "*name*".fmt(fmt),
+            Pattern::Regex { pattern } => pattern.fmt(fmt),
+            Pattern::Keyword { word } => write!(fmt, "*keyword* {}", word),
+            Pattern::NameWithArgs { name } => write!(fmt, "*name* {} *args*", name),
+        }
+    }
+}
+
+/// Serialize a regular expression.
+fn serialize_regex<S>(regex: &regex::Regex, s: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    s.collect_str(regex)
+}
+
+/// Find the first word in `input` which is equal to the lowercase `word`,
+/// ignoring case.
+///
+/// Words are separated by anything which isn't alphanumeric or an
+/// underscore, the same as a `\b` boundary in a regular expression.
+fn find_keyword<'a>(input: &'a str, word: &str) -> Option<&'a str> {
+    input
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .find(|w| !w.is_empty() && w.chars().flat_map(char::to_lowercase).eq(word.chars()))
+}
+
+#[derive(Debug)]
+pub enum Captures<'a> {
+    Prefix { rest: &'a str },
+    Regex { captures: regex::Captures<'a> },
+    Keyword { word: &'a str },
+    Args { full: &'a str, rest: &'a str },
+}
+
+impl<'a> Captures<'a> {
+    /// Get the number of captures.
+    fn len(&self) -> usize {
+        match self {
+            Self::Prefix { .. } | Self::Keyword { .. } => 1,
+            Self::Args { .. } => 2,
+            Self::Regex { captures, .. } => captures.len(),
+        }
+    }
+
+    /// Get the capture group at the given index.
+    ///
+    /// For prefix matches, index `0` is the rest of the input. For keyword
+    /// matches it is the matched word. For matches with arguments, index `0`
+    /// is the whole input and index `1` is the arguments.
+    pub fn get(&self, i: usize) -> Option<&'a str> {
+        match self {
+            Self::Prefix { rest } if i == 0 => Some(*rest),
+            Self::Keyword { word } if i == 0 => Some(*word),
+            Self::Args { full, .. } if i == 0 => Some(*full),
+            Self::Args { rest, .. } if i == 1 => Some(*rest),
+            Self::Prefix { .. } | Self::Keyword { .. } | Self::Args { .. } => None,
+            Self::Regex { captures, .. } => captures.get(i).map(|m| m.as_str()),
+        }
+    }
+
+    /// Get the capture group with the given name.
+    ///
+    /// For prefix matches and matches with arguments, the rest of the input
+    /// is named `rest`. For keyword matches, the matched word is named
+    /// `word`.
+    pub fn name(&self, name: &str) -> Option<&'a str> {
+        match self {
+            Self::Prefix { rest } | Self::Args { rest, .. } if name == "rest" => Some(*rest),
+            Self::Keyword { word } if name == "word" => Some(*word),
+            Self::Prefix { .. } | Self::Keyword { .. } | Self::Args { .. } => None,
+            Self::Regex { captures, .. } => captures.name(name).map(|m| m.as_str()),
+        }
+    }
+
+    /// Get the captures keyed by the given parameter names.
+    ///
+    /// Names are zipped with the captured arguments in order. For regular
+    /// expressions these are the groups following the whole match, for any
+    /// other match it is the single group that [name][Captures::name] exposes.
+    /// Groups which didn't participate in the match are omitted.
+    ///
+    /// If no names are given, every group is keyed by its index instead.
+    pub fn params(&self, names: &[String]) -> HashMap<String, &'a str> {
+        if names.is_empty() {
+            return (0..self.len())
+                .filter_map(|i| Some((i.to_string(), self.get(i)?)))
+                .collect();
+        }
+
+        let args = match self {
+            Self::Prefix { rest } | Self::Args { rest, .. } => vec![Some(*rest)],
+            Self::Keyword { word } => vec![Some(*word)],
+            Self::Regex { captures } => captures
+                .iter()
+                .skip(1)
+                .map(|g| g.map(|m| m.as_str()))
+                .collect(),
+        };
+
+        names
+            .iter()
+            .zip(args)
+            .filter_map(|(name, arg)| Some((name.clone(), arg?)))
+            .collect()
+    }
+
+    /// Expand references to capture groups in the given template, like `$1`
+    /// or `$name`, using the syntax of [regex::Captures::expand].
+    ///
+    /// Only regular expression matches are expanded, for any other match the
+    /// template is returned unchanged.
+    pub fn expand(&self, template: &str) -> String {
+        match self {
+            Self::Prefix { .. } | Self::Keyword { .. } | Self::Args { .. } => template.to_string(),
+            Self::Regex { captures } => {
+                let mut out = String::new();
+                captures.expand(template, &mut out);
+                out
+            }
+        }
+    }
+}
+
+/// Captures which own their groups, as returned by
+/// [resolve_owned][Matcher::resolve_owned].
+#[derive(Debug, Clone)]
+pub struct OwnedCaptures {
+    /// Capture groups by index.
+    groups: Vec<Option<String>>,
+    /// Indexes of named capture groups.
+    names: HashMap<String, usize>,
+}
+
+impl OwnedCaptures {
+    /// Construct owned captures from the captures of the given pattern.
+    fn new(pattern: &Pattern, captures: &Captures<'_>) -> Self {
+        let names = match pattern {
+            Pattern::Regex { pattern } => pattern
+                .capture_names()
+                .enumerate()
+                .filter_map(|(i, name)| Some((name?.to_string(), i)))
+                .collect(),
+            Pattern::Name => Some((String::from("rest"), 0)).into_iter().collect(),
+            Pattern::Keyword { .. } => Some((String::from("word"), 0)).into_iter().collect(),
+            Pattern::NameWithArgs { .. } => Some((String::from("rest"), 1)).into_iter().collect(),
+        };
+
+        let groups = (0..captures.len())
+            .map(|i| captures.get(i).map(String::from))
+            .collect();
+
+        Self { groups, names }
+    }
+
+    /// Get the capture group at the given index.
+    ///
+    /// For prefix matches, index `0` is the rest of the input.
+    pub fn get(&self, i: usize) -> Option<&str> {
+        self.groups.get(i)?.as_deref()
+    }
+
+    /// Get the capture group with the given name.
+    ///
+    /// For prefix matches, the rest of the input is named `rest`.
+    pub fn name(&self, name: &str) -> Option<&str> {
+        self.get(*self.names.get(name)?)
+    }
+}
+
+/// Renders captured groups as `[0]="...", [1]="..."`, with groups which did
+/// not participate in the match omitted.
+impl fmt::Display for Captures<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Prefix { rest } => write!(fmt, "[rest]={:?}", rest),
+            Self::Keyword { word } => write!(fmt, "[word]={:?}", word),
+            Self::Args { full, rest } => write!(fmt, "[0]={:?}, [1]={:?}", full, rest),
+            Self::Regex { captures } => {
+                let groups = captures
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, g)| Some((i, g?.as_str())));
+
+                for (n, (i, g)) in groups.enumerate() {
+                    if n > 0 {
+                        write!(fmt, ", ")?;
+                    }
+
+                    write!(fmt, "[{}]={:?}", i, g)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+impl serde::Serialize for Captures<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap as _;
+
+        let mut m = serializer.serialize_map(Some(self.len()))?;
+
+        match self {
+            Self::Prefix { rest } => {
+                m.serialize_entry("rest", rest)?;
+            }
+            Self::Keyword { word } => {
+                m.serialize_entry("word", word)?;
+            }
+            Self::Args { full, rest } => {
+                m.serialize_entry(&0, full)?;
+                m.serialize_entry(&1, rest)?;
+            }
+            Self::Regex { captures, .. } => {
+                for (i, g) in captures.iter().enumerate() {
+                    m.serialize_entry(&i, &g.map(|m| m.as_str()))?;
+                }
+            }
+        }
+
+        m.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        edit_distance, Captures, Collision, Key, MatchKind, Matchable, Matcher, Pattern, RateLimit,
+        ResolveError, ResolveStrategy,
+    };
+    use crate::utils;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::collections::HashSet;
+    use std::sync::{Arc, RwLock};
+    use std::time::{Duration, Instant};
+
+    /// Counts the allocations made by every thread separately, so that tests
+    /// running in parallel don't affect each other.
+    struct CountingAlloc;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = Cell::new(0);
+    }
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOC: CountingAlloc = CountingAlloc;
+
+    /// Count the allocations made by the current thread while calling `f`.
+    fn allocations<F, R>(f: F) -> (R, usize)
+    where
+        F: FnOnce() -> R,
+    {
+        let before = ALLOCATIONS.with(Cell::get);
+        let output = f();
+        (output, ALLOCATIONS.with(Cell::get) - before)
+    }
+
+    #[derive(Debug, Clone)]
+    struct Entry {
+        key: Key,
+        pattern: Pattern,
+        namespace: Option<String>,
+        rate_limit: Option<RateLimit>,
+        tags: Vec<String>,
+        disabled: bool,
+        params: Vec<String>,
+    }
+
+    impl Matchable for Entry {
+        fn key(&self) -> &Key {
+            &self.key
+        }
+
+        fn key_mut(&mut self) -> &mut Key {
+            &mut self.key
+        }
+
+        fn pattern(&self) -> &Pattern {
+            &self.pattern
+        }
+
+        fn namespace(&self) -> Option<&str> {
+            self.namespace.as_deref()
+        }
+
+        fn rate_limit(&self) -> Option<&RateLimit> {
+            self.rate_limit.as_ref()
+        }
+
+        fn tags(&self) -> &[String] {
+            &self.tags
+        }
+
+        fn is_enabled(&self) -> bool {
+            !self.disabled
+        }
+
+        fn set_enabled(&mut self, enabled: bool) {
+            self.disabled = !enabled;
+        }
+
+        fn params(&self) -> &[String] {
+            &self.params
+        }
+    }
+
+    fn entry(channel: &str, name: &str, pattern: Option<&str>) -> (Key, Arc<Entry>) {
+        let key = Key::new(channel, name);
+        let pattern = Pattern::from_db(pattern).expect("valid pattern");
+
+        let entry = Entry {
+            key: key.clone(),
+            pattern,
+            namespace: None,
+            rate_limit: None,
+            tags: Vec::new(),
+            disabled: false,
+            params: Vec::new(),
+        };
+
+        (key, Arc::new(entry))
+    }
+
+    #[test]
+    fn test_concurrent_modify_and_resolve() {
+        let matcher = Arc::new(RwLock::new(Matcher::new()));
+
+        {
+            let (key, value) = entry("#test", "hello", None);
+            matcher.write().unwrap().insert(key, value);
+        }
+
+        let writer = std::thread::spawn({
+            let matcher = matcher.clone();
+
+            move || {
+                for i in 0..1000 {
+                    let pattern = if i % 2 == 0 {
+                        Pattern::regex(regex::Regex::new("^hello").unwrap())
+                    } else {
+                        Pattern::Name
+                    };
+
+                    let key = Key::new("#test", "hello");
+                    let mut matcher = matcher.write().unwrap();
+                    assert!(matcher.modify(key, move |e| e.pattern = pattern));
+                }
+            }
+        });
+
+        let readers = (0..4)
+            .map(|_| {
+                let matcher = matcher.clone();
+
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        let mut it = utils::Words::new("hello world");
+                        let first = it.next();
+
+                        let matcher = matcher.read().unwrap();
+
+                        let (entry, captures) = matcher
+                            .resolve("#test", first.as_deref(), &it)
+                            .expect("entry to always resolve");
+
+                        match (entry.pattern(), captures) {
+                            (Pattern::Name, Captures::Prefix { .. }) => (),
+                            (Pattern::Regex { .. }, Captures::Regex { .. }) => (),
+                            (pattern, captures) => {
+                                panic!("inconsistent resolve: {} / {:?}", pattern, captures)
+                            }
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        writer.join().unwrap();
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    fn resolves(matcher: &Matcher<Entry>, channel: &str, input: &'static str) -> Option<Key> {
+        let mut it = utils::Words::new(input);
+        let first = it.next();
+        let (entry, _) = matcher.resolve(channel, first.as_deref(), &it)?;
+        Some(entry.key.clone())
+    }
+
+    fn resolves_prefix(
+        matcher: &Matcher<Entry>,
+        channel: &str,
+        input: &'static str,
+    ) -> Option<Key> {
+        let mut it = utils::Words::new(input);
+        let first = it.next();
+        let (entry, _) = matcher.resolve_prefix(channel, first.as_deref(), &it)?;
+        Some(entry.key.clone())
+    }
+
+    #[test]
+    fn test_migrate_channel() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#a", "hello", None),
+            entry("#a", "trigger", Some("^trig+er")),
+            entry("#b", "other", None),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        let moved = matcher
+            .migrate_channel("#a", "#b", Collision::Error)
+            .unwrap();
+        assert_eq!(2, moved.len());
+
+        assert!(resolves(&matcher, "#a", "hello").is_none());
+        assert!(resolves(&matcher, "#a", "triggger").is_none());
+        assert_eq!(
+            Some(Key::new("#b", "hello")),
+            resolves(&matcher, "#b", "hello")
+        );
+        assert_eq!(
+            Some(Key::new("#b", "trigger")),
+            resolves(&matcher, "#b", "triggger")
+        );
+        assert_eq!(
+            Some(Key::new("#b", "other")),
+            resolves(&matcher, "#b", "other")
+        );
+
+        let entry = matcher.get(&Key::new("#b", "trigger")).unwrap();
+        assert_eq!(&Key::new("#b", "trigger"), entry.key());
+    }
+
+    #[test]
+    fn test_move_expiring() {
+        let mut matcher = Matcher::new();
+        let expires_at = Instant::now() + Duration::from_secs(60);
+
+        let (key, value) = entry("#a", "hello", None);
+        matcher.insert_expiring(key, value, expires_at);
+
+        matcher
+            .migrate_channel("#a", "#b", Collision::Error)
+            .unwrap();
+        matcher
+            .rename(&Key::new("#b", "hello"), Key::new("#b", "world"))
+            .unwrap();
+
+        assert!(matcher.get(&Key::new("#b", "hello")).is_none());
+        assert_eq!(
+            Some(Key::new("#b", "world")),
+            resolves(&matcher, "#b", "world")
+        );
+        assert_eq!(
+            vec![Key::new("#b", "world")],
+            matcher.sweep_expired(expires_at)
+        );
+    }
+
+    #[test]
+    fn test_migrate_channel_collision() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#a", "hello", None),
+            entry("#a", "trigger", Some("^trig+er")),
+            entry("#b", "hello", Some("^hello")),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        assert!(matcher
+            .migrate_channel("#a", "#b", Collision::Error)
+            .is_err());
+        assert_eq!(
+            Some(Key::new("#a", "hello")),
+            resolves(&matcher, "#a", "hello")
+        );
+        assert_eq!(
+            Some(Key::new("#a", "trigger")),
+            resolves(&matcher, "#a", "trigger")
+        );
+
+        let moved = matcher
+            .migrate_channel("#a", "#b", Collision::Skip)
+            .unwrap();
+        assert_eq!(
+            vec![(Key::new("#a", "trigger"), Key::new("#b", "trigger"))],
+            moved
+        );
+        assert_eq!(
+            Some(Key::new("#a", "hello")),
+            resolves(&matcher, "#a", "hello")
+        );
+        assert_eq!(
+            Some(Key::new("#b", "hello")),
+            resolves(&matcher, "#b", "hello")
+        );
+        assert_eq!(
+            Some(Key::new("#b", "trigger")),
+            resolves(&matcher, "#b", "trigger")
+        );
+        assert!(matcher.get(&Key::new("#a", "trigger")).is_none());
+    }
+
+    #[test]
+    fn test_resolve_owned() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "hello", None),
+            entry("#test", "trigger", Some("^trig+er (?P<what>\\w+)")),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        let (entry, captures) = {
+            let mut it = utils::Words::new("hello big world");
+            let first = it.next();
+            matcher
+                .resolve_owned("#test", first.as_deref(), &it)
+                .unwrap()
+        };
+
+        assert_eq!(&Key::new("#test", "hello"), entry.key());
+        assert_eq!(Some("big world"), captures.name("rest"));
+
+        let (entry, captures) = {
+            let mut it = utils::Words::new("triggger happy");
+            let first = it.next();
+            matcher
+                .resolve_owned("#test", first.as_deref(), &it)
+                .unwrap()
+        };
+
+        assert_eq!(&Key::new("#test", "trigger"), entry.key());
+        assert_eq!(Some("triggger happy"), captures.get(0));
+        assert_eq!(Some("happy"), captures.name("what"));
+        assert_eq!(None, captures.name("rest"));
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(0, edit_distance("hello", "hello"));
+        assert_eq!(1, edit_distance("helo", "hello"));
+        assert_eq!(3, edit_distance("kitten", "sitting"));
+        assert_eq!(5, edit_distance("", "hello"));
+    }
+
+    #[test]
+    fn test_suggest() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "hello", None),
+            entry("#test", "help", None),
+            entry("#test", "world", None),
+            entry("#test", "trigger", Some("^trig+er")),
+            entry("#other", "hell", None),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        assert_eq!(vec!["hello", "help"], matcher.suggest("#test", "Helo", 2));
+        assert_eq!(
+            vec!["hello", "help", "world"],
+            matcher.suggest("#test", "helo", 10)
+        );
+    }
+
+    #[test]
+    fn test_miss_handler() {
+        let misses = Arc::new(RwLock::new(Vec::new()));
+
+        let mut matcher = Matcher::new();
+        let (key, value) = entry("#test", "hello", None);
+        matcher.insert(key, value);
+
+        matcher.set_miss_handler({
+            let misses = misses.clone();
+
+            move |channel, first| {
+                misses
+                    .write()
+                    .unwrap()
+                    .push((channel.to_string(), first.to_string()));
+            }
+        });
+
+        assert!(resolves(&matcher, "#test", "hello").is_some());
+        assert!(resolves(&matcher, "#test", "helo world").is_none());
+        assert!(resolves(&matcher, "#test", "").is_none());
+
+        assert_eq!(
+            vec![(String::from("#test"), String::from("helo"))],
+            *misses.read().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_resolve_allocations() {
+        let mut matcher = Matcher::new();
+        let (key, value) = entry("#test", "hello", None);
+        matcher.insert(key, value);
+
+        let it = utils::Words::new("world");
+
+        // NB: this is what looking up a name used to cost.
+        let (_, n) = allocations(|| Key::new("#test", "hello"));
+        assert!(n > 0);
+
+        let (resolved, n) = allocations(|| matcher.resolve("#test", Some("hello"), &it).is_some());
+        assert!(resolved);
+        assert_eq!(0, n);
+
+        let (resolved, n) =
+            allocations(|| matcher.resolve_lower("#test", Some("hello"), &it).is_some());
+        assert!(resolved);
+        assert_eq!(0, n);
+
+        // input which isn't lowercase still has to be lowercased.
+        let (resolved, n) = allocations(|| matcher.resolve("#test", Some("Hello"), &it).is_some());
+        assert!(resolved);
+        assert!(n > 0);
+    }
+
+    /// Benchmark resolving a line which doesn't match anything, with and
+    /// without skipping regular expressions.
+    ///
+    /// Run with `cargo test --release bench_skip_regex -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_skip_regex() {
+        const ITERATIONS: u32 = 100_000;
+
+        let mut matcher = Matcher::new();
+
+        for i in 0..100 {
+            let pattern = format!("^foo{} (?P<bar>\\w+)", i);
+
+            for (key, value) in vec![
+                entry("#names", &format!("name{}", i), None),
+                entry("#regex", &format!("name{}", i), None),
+                entry("#regex", &format!("pattern{}", i), Some(&pattern)),
+            ] {
+                matcher.insert(key, value);
+            }
+        }
+
+        let mut it = utils::Words::new("just some chat line");
+        let first = it.next();
+
+        let bench = |name: &str, matcher: &Matcher<Entry>, channel: &str| {
+            let start = Instant::now();
+
+            for _ in 0..ITERATIONS {
+                assert!(matcher.resolve(channel, first.as_deref(), &it).is_none());
+            }
+
+            println!("{}: {:?}/resolve", name, start.elapsed() / ITERATIONS);
+        };
+
+        bench("names", &matcher, "#names");
+        bench("regex", &matcher, "#regex");
+
+        matcher.set_skip_regex(Some("#names"), true);
+        matcher.set_skip_regex(Some("#regex"), true);
+
+        bench("names (skipped)", &matcher, "#names");
+        bench("regex (skipped)", &matcher, "#regex");
+    }
+
+    #[test]
+    fn test_skip_regex() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#a", "hello", None),
+            entry("#a", "trigger", Some("^trig+er")),
+            entry("#b", "trigger", Some("^trig+er")),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        matcher.set_skip_regex(Some("#a"), true);
+        assert!(resolves(&matcher, "#a", "hello").is_some());
+        assert!(resolves(&matcher, "#a", "triggger").is_none());
+        assert!(resolves(&matcher, "#b", "triggger").is_some());
+
+        matcher.set_skip_regex(None, true);
+        assert!(resolves(&matcher, "#b", "triggger").is_none());
+
+        matcher.set_skip_regex(None, false);
+        matcher.set_skip_regex(Some("#a"), false);
+        assert!(resolves(&matcher, "#a", "triggger").is_some());
+    }
+
+    #[test]
+    fn test_expiring() {
+        let mut matcher = Matcher::new();
+        let now = Instant::now();
+
+        let (key, value) = entry("#test", "hello", None);
+        matcher.insert_expiring(key, value, now + Duration::from_secs(60));
+        let (key, value) = entry("#test", "trigger", Some("^trig+er"));
+        matcher.insert_expiring(key, value, now);
+        let (key, value) = entry("#test", "world", None);
+        matcher.insert(key, value);
+
+        assert!(resolves(&matcher, "#test", "hello").is_some());
+        assert!(resolves(&matcher, "#test", "triggger").is_none());
+
+        assert_eq!(
+            vec![Key::new("#test", "trigger")],
+            matcher.sweep_expired(now)
+        );
+        assert!(matcher.get(&Key::new("#test", "trigger")).is_none());
+
+        let mut expired = matcher.sweep_expired(now + Duration::from_secs(120));
+        expired.sort();
+        assert_eq!(vec![Key::new("#test", "hello")], expired);
+        assert!(resolves(&matcher, "#test", "world").is_some());
+    }
+
+    #[test]
+    fn test_captures_expand() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "hello", None),
+            entry("#test", "trigger", Some("^trig+er (?P<what>\\w+) (\\w+)")),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        let mut it = utils::Words::new("hello $1");
+        let first = it.next();
+        let (_, captures) = matcher.resolve("#test", first.as_deref(), &it).unwrap();
+        assert_eq!("got $1", captures.expand("got $1"));
+
+        let mut it = utils::Words::new("triggger happy day");
+        let first = it.next();
+        let (_, captures) = matcher.resolve("#test", first.as_deref(), &it).unwrap();
+        assert_eq!("happy, day", captures.expand("$what, $2"));
+    }
+
+    #[test]
+    fn test_regex_disabled() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "hello", None),
+            entry("#test", "trigger", Some("^trig+er")),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        matcher.set_regex_enabled(false);
+        assert_eq!(
+            Some(Key::new("#test", "hello")),
+            resolves(&matcher, "#test", "hello")
+        );
+        assert!(resolves(&matcher, "#test", "triggger").is_none());
+
+        matcher.set_regex_enabled(true);
+        assert_eq!(
+            Some(Key::new("#test", "trigger")),
+            resolves(&matcher, "#test", "triggger")
+        );
+    }
+
+    #[test]
+    fn test_get_with_kind() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "hello", None),
+            entry("#test", "trigger", Some("^trig+er")),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        let key = Key::new("#test", "hello");
+        assert_eq!(
+            Some(MatchKind::Name),
+            matcher.get_with_kind(&key).map(|(_, k)| k)
+        );
+
+        let key = Key::new("#test", "trigger");
+        assert_eq!(
+            Some(MatchKind::Regex),
+            matcher.get_with_kind(&key).map(|(_, k)| k)
+        );
+
+        let pattern = Pattern::Name;
+        assert!(matcher.modify(key.clone(), move |e| e.pattern = pattern));
+        assert_eq!(
+            Some(MatchKind::Name),
+            matcher.get_with_kind(&key).map(|(_, k)| k)
+        );
+
+        assert!(matcher
+            .get_with_kind(&Key::new("#test", "missing"))
+            .is_none());
+    }
+
+    #[test]
+    fn test_resolve_filtered() {
+        let mut matcher = Matcher::new();
+
+        for ((key, value), tags) in vec![
+            (entry("#test", "hello", None), vec!["fun"]),
+            (entry("#test", "trigger", Some("^trig+er")), vec!["mod"]),
+            (entry("#test", "untagged", None), vec![]),
+        ] {
+            let mut value = (*value).clone();
+            value.tags = tags.into_iter().map(String::from).collect();
+            matcher.insert(key, Arc::new(value));
+        }
+
+        let allowed = Some(String::from("mod"))
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        let resolves_filtered = |input: &'static str| {
+            let mut it = utils::Words::new(input);
+            let first = it.next();
+            let (entry, _) = matcher.resolve_filtered("#test", first.as_deref(), &it, &allowed)?;
+            Some(entry.key.clone())
+        };
+
+        assert!(resolves_filtered("hello").is_none());
+        assert!(resolves_filtered("untagged").is_none());
+        assert_eq!(
+            Some(Key::new("#test", "trigger")),
+            resolves_filtered("triggger")
+        );
+        assert!(resolves(&matcher, "#test", "hello").is_some());
+    }
+
+    #[test]
+    fn test_replace_all() {
+        let mut matcher = Matcher::new();
+        matcher.set_slow_threshold(Some(Duration::from_millis(10)));
+
+        let (key, value) = entry("#test", "hello", None);
+        matcher.insert(key, value);
+
+        matcher.replace_all(vec![
+            entry("#test", "world", None),
+            entry("#test", "trigger", Some("^trig+er")),
+        ]);
+
+        assert!(resolves(&matcher, "#test", "hello").is_none());
+        assert_eq!(
+            Some(Key::new("#test", "world")),
+            resolves(&matcher, "#test", "world")
+        );
+        assert_eq!(
+            Some(Key::new("#test", "trigger")),
+            resolves(&matcher, "#test", "triggger")
+        );
+        assert_eq!(Some(Duration::from_millis(10)), matcher.slow_threshold);
+    }
+
+    #[test]
+    fn test_pattern_case_insensitive() {
+        let mut matcher = Matcher::new();
+
+        let (key, value) = entry("#test", "hello", Some("^hello"));
+        matcher.insert(key, value);
+
+        assert!(resolves(&matcher, "#test", "hello").is_some());
+        assert!(resolves(&matcher, "#test", "Hello").is_some());
+        assert!(resolves(&matcher, "#test", "HELLO world").is_some());
+
+        let (key, value) = entry("#test", "hello", Some("(?-i)^hello"));
+        matcher.insert(key, value);
+
+        assert!(resolves(&matcher, "#test", "hello").is_some());
+        assert!(resolves(&matcher, "#test", "Hello").is_none());
+    }
+
+    #[test]
+    fn test_resolve_longest_match() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "broad", Some("hello")),
+            entry("#test", "specific", Some("super hello")),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        matcher.set_strategy(ResolveStrategy::LongestMatch);
+
+        for _ in 0..10 {
+            assert_eq!(
+                Some(Key::new("#test", "specific")),
+                resolves(&matcher, "#test", "say super hello")
+            );
+        }
+
+        assert_eq!(
+            Some(Key::new("#test", "broad")),
+            resolves(&matcher, "#test", "say hello")
+        );
+    }
+
+    #[test]
+    fn test_resolve_in_namespace() {
+        let mut matcher = Matcher::new();
+
+        let (key, value) = entry("#test", "responder", Some("hello"));
+        matcher.insert(key, value);
+
+        let (key, value) = entry("#test", "moderation", Some("hello"));
+        let mut value = (*value).clone();
+        value.namespace = Some(String::from("moderation"));
+        matcher.insert(key, Arc::new(value));
+
+        for _ in 0..10 {
+            assert_eq!(
+                Some(Key::new("#test", "responder")),
+                resolves(&matcher, "#test", "hello")
+            );
+
+            let (entry, _) = matcher
+                .resolve_in_namespace("#test", Some("moderation"), "hello")
+                .unwrap();
+            assert_eq!(Key::new("#test", "moderation"), entry.key);
+        }
+
+        assert!(matcher
+            .resolve_in_namespace("#test", Some("other"), "hello")
+            .is_none());
+    }
+
+    #[test]
+    fn test_iter_sorted() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#b", "a", None),
+            entry("#a", "c", None),
+            entry("#a", "b", Some("b")),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        let keys = matcher
+            .iter_sorted()
+            .into_iter()
+            .map(|(key, _)| key.to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["#a/b", "#a/c", "#b/a"], keys);
+    }
+
+    #[test]
+    fn test_resolve_unique_prefix() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "song", None),
+            entry("#test", "uptime", None),
+            entry("#other", "sound", None),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        assert_eq!(
+            Some(Key::new("#test", "song")),
+            resolves_prefix(&matcher, "#test", "so current")
+        );
+        assert_eq!(
+            Some(Key::new("#test", "song")),
+            resolves_prefix(&matcher, "#test", "song")
+        );
+        assert!(resolves(&matcher, "#test", "so current").is_none());
+    }
+
+    #[test]
+    fn test_resolve_ambiguous_prefix() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "song", None),
+            entry("#test", "sound", None),
+            entry("#test", "so", None),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        assert!(resolves_prefix(&matcher, "#test", "s").is_none());
+        assert!(resolves_prefix(&matcher, "#test", "sou").is_some());
+        // exact matches win over prefixes.
+        assert_eq!(
+            Some(Key::new("#test", "so")),
+            resolves_prefix(&matcher, "#test", "so")
+        );
+    }
+
+    #[test]
+    fn test_resolve_no_prefix() {
+        let mut matcher = Matcher::new();
+
+        let (key, value) = entry("#test", "song", None);
+        matcher.insert(key, value);
+
+        assert!(resolves_prefix(&matcher, "#test", "x").is_none());
+        assert!(resolves_prefix(&matcher, "#test", "songs").is_none());
+        assert!(resolves_prefix(&matcher, "#other", "so").is_none());
+    }
+
+    #[test]
+    fn test_explain() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "song", None),
+            entry("#test", "greeting", Some("^hello (?P<who>\\w+)")),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        let it = utils::Words::new("hello world");
+        let explanation = matcher.explain("#test", None, &it);
+        assert_eq!(Some(Key::new("#test", "greeting")), explanation.key);
+        assert!(matches!(explanation.pattern, Some(Pattern::Regex { .. })));
+        let captures = explanation.captures.expect("captures");
+        assert_eq!(Some("world"), captures.name("who"));
+
+        let mut it = utils::Words::new("song current");
+        let first = it.next();
+        let explanation = matcher.explain("#test", first.as_deref(), &it);
+        assert_eq!(Some(Key::new("#test", "song")), explanation.key);
+        assert!(matches!(explanation.pattern, Some(Pattern::Name)));
+        let captures = explanation.captures.expect("captures");
+        assert_eq!(Some("current"), captures.get(0));
+
+        let it = utils::Words::new("nothing here");
+        let explanation = matcher.explain("#test", None, &it);
+        assert!(explanation.key.is_none());
+        assert!(explanation.captures.is_none());
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "song", None),
+            entry("#test", "sound", Some("^sound")),
+            entry("#test", "uptime", Some("^uptime")),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        matcher.retain(|key, _| !key.name.starts_with("so"));
+
+        assert!(matcher.get(&Key::new("#test", "song")).is_none());
+        assert!(matcher.get(&Key::new("#test", "sound")).is_none());
+        assert!(resolves(&matcher, "#test", "song").is_none());
+        assert!(resolves(&matcher, "#test", "sound").is_none());
+        assert_eq!(
+            Some(Key::new("#test", "uptime")),
+            resolves(&matcher, "#test", "uptime")
+        );
+
+        matcher.retain(|_, value| matches!(value.pattern(), Pattern::Name));
+        assert!(resolves(&matcher, "#test", "uptime").is_none());
+        assert_eq!(0, matcher.iter().count());
+    }
+
+    #[test]
+    fn test_insert_all_collects_errors() {
+        let mut matcher = Matcher::new();
+
+        let values = vec![
+            ("hello", Some("^hello")),
+            ("broken", Some("^(unclosed")),
+            ("name", None),
+        ]
+        .into_iter()
+        .map(|(name, pattern)| {
+            let key = Key::new("#test", name);
+
+            let entry = Pattern::from_db(pattern).map(|pattern| Entry {
+                key: key.clone(),
+                pattern,
+                namespace: None,
+                rate_limit: None,
+                tags: Vec::new(),
+                disabled: false,
+                params: Vec::new(),
+            });
+
+            (key, entry)
+        });
+
+        let errors = matcher.insert_all(values);
+
+        assert_eq!(1, errors.len());
+        assert_eq!(Key::new("#test", "broken"), errors[0].0);
+        assert!(resolves(&matcher, "#test", "hello").is_some());
+        assert!(resolves(&matcher, "#test", "name").is_some());
+        assert!(matcher.get(&Key::new("#test", "broken")).is_none());
+    }
+
+    #[test]
+    fn test_captures_display() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "song", None),
+            entry("#test", "greeting", Some("^(hello|bye)( there)? (\\w+)")),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        let it = utils::Words::new("hello world");
+        let (_, captures) = matcher.resolve("#test", None, &it).unwrap();
+        assert_eq!(
+            r#"[0]="hello world", [1]="hello", [3]="world""#,
+            captures.to_string()
+        );
+
+        let mut it = utils::Words::new("song current");
+        let first = it.next();
+        let (_, captures) = matcher.resolve("#test", first.as_deref(), &it).unwrap();
+        assert_eq!(r#"[rest]="current""#, captures.to_string());
+    }
+
+    #[test]
+    fn test_resolve_lower() {
+        let mut matcher = Matcher::new();
+
+        let (key, value) = entry("#test", "song", None);
+        matcher.insert(key, value);
+
+        let resolves_lower = |input: &'static str| {
+            let mut it = utils::Words::new(input);
+            let first = it.next();
+            let (entry, _) = matcher.resolve_lower("#test", first.as_deref(), &it)?;
+            Some(entry.key.clone())
+        };
+
+        assert_eq!(Some(Key::new("#test", "song")), resolves_lower("song"));
+        assert!(resolves_lower("Song").is_none());
+        assert_eq!(
+            Some(Key::new("#test", "song")),
+            resolves(&matcher, "#test", "Song")
+        );
+        assert_eq!(
+            Some(Key::new("#test", "song")),
+            resolves(&matcher, "#test", "song")
+        );
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut matcher = Matcher::new();
+
+        let (key, value) = entry("#test", "song", None);
+        let mut value = (*value).clone();
+        value.rate_limit = Some(RateLimit::new(2, Duration::from_secs(60)));
+        matcher.insert(key, Arc::new(value));
+
+        for (key, value) in vec![
+            entry("#test", "limited", Some("^hello")),
+            entry("#test", "fallback", Some("^hello world")),
+        ] {
+            let mut value = (*value).clone();
+
+            if key.name == "limited" {
+                value.rate_limit = Some(RateLimit::new(1, Duration::from_secs(60)));
+            }
+
+            matcher.insert(key, Arc::new(value));
+        }
+
+        // explaining doesn't count towards the limit.
+        let it = utils::Words::new("song");
+        assert!(matcher.explain("#test", Some("song"), &it).key.is_some());
+
+        assert!(resolves(&matcher, "#test", "song").is_some());
+        assert!(resolves(&matcher, "#test", "song").is_some());
+        assert!(resolves(&matcher, "#test", "song").is_none());
+
+        assert_eq!(
+            Some(Key::new("#test", "limited")),
+            resolves(&matcher, "#test", "hello")
+        );
+        assert!(resolves(&matcher, "#test", "hello").is_none());
+        assert_eq!(
+            Some(Key::new("#test", "fallback")),
+            resolves(&matcher, "#test", "hello world")
+        );
+    }
+
+    #[test]
+    fn test_resolve_with_clean() {
+        let mut matcher = Matcher::new();
+
+        let (key, value) = entry("#test", "greeting", Some("^hello (?P<who>\\w+)$"));
+        matcher.insert(key, value);
+
+        let it = utils::Words::new("hello Kappa world");
+        assert!(matcher.resolve("#test", None, &it).is_none());
+
+        let (entry, captures) = matcher
+            .resolve_with_clean("#test", None, "hello world", &it)
+            .expect("clean input to match");
+        assert_eq!(Key::new("#test", "greeting"), entry.key);
+        assert_eq!(Some("world"), captures.name("who"));
+
+        let it = utils::Words::new("hello world");
+        let (_, captures) = matcher
+            .resolve_with_clean("#test", None, "hello world", &it)
+            .expect("clean input to match");
+        assert_eq!(Some("hello world"), captures.get(0));
+    }
+
+    #[test]
+    fn test_contains_regex() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "greeting", Some("^hello")),
+            entry("#test", "hello", None),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        assert!(matcher.contains_regex("#test", "^hello"));
+        assert!(!matcher.contains_regex("#test", "hello"));
+        assert!(!matcher.contains_regex("#other", "^hello"));
+
+        let greeting = Key::new("#test", "greeting");
+        let hello = Key::new("#test", "hello");
+        assert!(!matcher.contains_regex_except("#test", &greeting, "^hello"));
+        assert!(matcher.contains_regex_except("#test", &hello, "^hello"));
+    }
+
+    #[test]
+    fn test_keyword() {
+        let mut matcher = Matcher::new();
+
+        let key = Key::new("#test", "pog");
+
+        let value = Arc::new(Entry {
+            key: key.clone(),
+            pattern: Pattern::keyword("PogChamp"),
+            namespace: None,
+            rate_limit: None,
+            tags: Vec::new(),
+            disabled: false,
+            params: Vec::new(),
+        });
+
+        matcher.insert(key.clone(), value);
+        assert_eq!(
+            Some(MatchKind::Keyword),
+            matcher.get_with_kind(&key).map(|(_, k)| k)
+        );
+
+        let it = utils::Words::new("that was pogchamp!");
+        let (entry, captures) = matcher
+            .resolve("#test", None, &it)
+            .expect("keyword to match");
+        assert_eq!(key, entry.key);
+        assert_eq!(Some("pogchamp"), captures.get(0));
+        assert_eq!(Some("pogchamp"), captures.name("word"));
+
+        for input in &["pogchamps", "notpogchamp", "pog_champ", ""] {
+            let it = utils::Words::new(*input);
+            assert!(matcher.resolve("#test", None, &it).is_none(), "{}", input);
+        }
+
+        assert!(matcher
+            .resolve("#other", None, &utils::Words::new("pogchamp"))
+            .is_none());
+    }
+
+    #[test]
+    fn test_key_normalized() {
+        assert_eq!(Key::new("#Foo", "Hello"), Key::new("foo", "hello"));
+        assert_eq!(Key::new("#Foo", "hello").channel, "#foo");
+        assert_eq!(Key::new("foo", "hello").channel, "#foo");
+
+        let mut matcher = Matcher::new();
+        let key = Key::new("#Foo", "hello");
+
+        let value = Arc::new(Entry {
+            key: key.clone(),
+            pattern: Pattern::Name,
+            namespace: None,
+            rate_limit: None,
+            tags: Vec::new(),
+            disabled: false,
+            params: Vec::new(),
+        });
+
+        matcher.insert(key, value);
+        assert!(matcher.get(&Key::new("foo", "HELLO")).is_some());
+    }
+
+    #[test]
+    fn test_resolve_normalized_channel() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#foo", "hello", None),
+            entry("#foo", "bye", Some("^bye")),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        let hello = Some(Key::new("#foo", "hello"));
+        let bye = Some(Key::new("#foo", "bye"));
+
+        for channel in &["#foo", "foo", "#Foo", "FOO"] {
+            assert_eq!(hello, resolves(&matcher, channel, "hello"));
+            assert_eq!(bye, resolves(&matcher, channel, "bye now"));
+            assert!(matcher.contains_regex(channel, "^bye"));
+            assert_eq!(vec!["hello"], matcher.suggest(channel, "helo", 1));
+        }
+
+        let it = utils::Words::new("bye now");
+        assert_eq!(bye, matcher.explain("#FOO", None, &it).key);
+    }
+
+    #[test]
+    fn test_set_channel_enabled() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "hello", None),
+            entry("#test", "greeting", Some("^hi")),
+            entry("#other", "hello", None),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        assert_eq!(2, matcher.set_channel_enabled("#test", false));
+        assert_eq!(0, matcher.set_channel_enabled("#test", false));
+
+        let it = utils::Words::new("hello");
+        assert!(matcher.resolve("#test", Some("hello"), &it).is_none());
+        assert!(matcher.resolve("#other", Some("hello"), &it).is_some());
+
+        let it = utils::Words::new("hi there");
+        assert!(matcher.resolve("#test", Some("hi"), &it).is_none());
+
+        assert_eq!(2, matcher.set_channel_enabled("#test", true));
+        assert!(matcher.resolve("#test", Some("hi"), &it).is_some());
+    }
+
+    #[test]
+    fn test_resolve_checked() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "hello", None),
+            entry("#test", "greeting", Some("^hello")),
+            entry("#test", "wave", Some("^hello world")),
+            entry("#test", "bye", Some("^bye")),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        let it = utils::Words::new("hello there");
+        let (entry, _) = matcher
+            .resolve_checked("#test", Some("hello"), &it)
+            .expect("names are never ambiguous")
+            .expect("name to match");
+        assert_eq!(Key::new("#test", "hello"), entry.key);
+
+        let it = utils::Words::new("bye now");
+        let (entry, _) = matcher
+            .resolve_checked("#test", Some("bye"), &it)
+            .expect("single pattern to match")
+            .expect("pattern to match");
+        assert_eq!(Key::new("#test", "bye"), entry.key);
+
+        let it = utils::Words::new("hello world");
+        match matcher.resolve_checked("#test", Some("hello"), &it) {
+            Ok(Some((entry, _))) => assert_eq!(Key::new("#test", "hello"), entry.key),
+            other => panic!("unexpected resolve: {:?}", other.map(|r| r.map(|(e, _)| e))),
+        }
+
+        let it = utils::Words::new("HELLO world");
+        match matcher.resolve_checked("#test", None, &it) {
+            Err(ResolveError::Ambiguous(keys)) => assert_eq!(
+                vec![Key::new("#test", "greeting"), Key::new("#test", "wave")],
+                keys
+            ),
+            other => panic!("unexpected resolve: {:?}", other.map(|r| r.map(|(e, _)| e))),
+        }
+
+        let it = utils::Words::new("nothing");
+        assert!(matches!(
+            matcher.resolve_checked("#test", None, &it),
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn test_insert_changed_pattern() {
+        let mut matcher = Matcher::new();
+
+        let (key, value) = entry("#test", "hello", Some("^hello"));
+        matcher.insert(key, value);
+        assert!(matcher.contains_regex("#test", "^hello"));
+
+        let (key, value) = entry("#test", "hello", None);
+        matcher.insert(key, value);
+        assert!(!matcher.contains_regex("#test", "^hello"));
+
+        assert_eq!(
+            Some(Key::new("#test", "hello")),
+            resolves(&matcher, "#test", "hello")
+        );
+
+        let mut value = (*entry("#test", "hello", None).1).clone();
+        value.pattern = Pattern::NameWithArgs {
+            name: String::from("!hi"),
+        };
+        matcher.insert(value.key.clone(), Arc::new(value));
+
+        assert_eq!(None, resolves(&matcher, "#test", "hello"));
+    }
+
+    #[test]
+    fn test_insert_name_with_args_collision() {
+        let mut matcher = Matcher::new();
+
+        let name_with_args = |name: &str| {
+            let mut value = (*entry("#test", name, None).1).clone();
+            value.pattern = Pattern::NameWithArgs {
+                name: String::from("!title"),
+            };
+            (value.key.clone(), Arc::new(value))
+        };
+
+        let (key, value) = name_with_args("title");
+        assert_eq!(None, matcher.insert(key, value));
+
+        // NB: re-inserting the same key is not a collision.
+        let (key, value) = name_with_args("title");
+        assert_eq!(None, matcher.insert(key, value));
+
+        let (key, value) = name_with_args("set-title");
+        assert_eq!(Some(Key::new("#test", "title")), matcher.insert(key, value));
+
+        assert_eq!(
+            Some(Key::new("#test", "set-title")),
+            resolves(&matcher, "#test", "!title foo")
+        );
+    }
+
+    #[test]
+    fn test_find_by_capture_name() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "hug", Some("^hug (?P<target>\\w+)")),
+            entry("#other", "slap", Some("^slap (?P<target>\\w+)")),
+            entry("#test", "greet", Some("^hello (?P<who>\\w+)")),
+            entry("#test", "target", None),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        assert_eq!(
+            vec![&Key::new("#other", "slap"), &Key::new("#test", "hug")],
+            matcher.find_by_capture_name("target")
+        );
+        assert!(matcher.find_by_capture_name("missing").is_empty());
+    }
+
+    #[test]
+    fn test_name_with_args() {
+        let mut matcher = Matcher::new();
+        let key = Key::new("#test", "set-title");
+
+        let value = Arc::new(Entry {
+            key: key.clone(),
+            pattern: Pattern::NameWithArgs {
+                name: String::from("!title"),
+            },
+            namespace: None,
+            rate_limit: None,
+            tags: Vec::new(),
+            disabled: false,
+            params: Vec::new(),
+        });
+
+        matcher.insert(key.clone(), value);
+        assert_eq!(
+            Some(MatchKind::NameWithArgs),
+            matcher.get_with_kind(&key).map(|(_, k)| k)
+        );
+
+        let mut it = utils::Words::new("!title Playing some games");
+        let first = it.next();
+
+        let (entry, captures) = matcher
+            .resolve("#test", first.as_deref(), &it)
+            .expect("name with args to match");
+        assert_eq!(key, entry.key);
+        assert_eq!(Some("Playing some games"), captures.get(1));
+        assert_eq!(Some("Playing some games"), captures.name("rest"));
+
+        let it = utils::Words::new("!title");
+        assert!(matcher.resolve("#other", Some("!title"), &it).is_none());
+
+        assert!(matcher.modify(key.clone(), |entry| entry.pattern = Pattern::Name));
+        assert!(matcher.resolve("#test", Some("!title"), &it).is_none());
+        assert!(matcher.resolve("#test", Some("set-title"), &it).is_some());
+    }
+
+    #[test]
+    fn test_override() {
+        let mut matcher = Matcher::new();
+
+        let (key, value) = entry("#test", "hello", None);
+        matcher.insert(key.clone(), value);
+
+        let (_, first) = entry("#test", "hello", Some("^hi"));
+        let (_, second) = entry("#test", "hello", Some("^yo"));
+        matcher.push_override(key.clone(), first);
+        matcher.push_override(key.clone(), second);
+
+        let it = utils::Words::new("hello there");
+        assert!(matcher.resolve("#test", Some("hello"), &it).is_none());
+
+        let it = utils::Words::new("yo there");
+        assert!(matcher.resolve("#test", Some("yo"), &it).is_some());
+        assert_eq!(
+            Some(MatchKind::Regex),
+            matcher.get_with_kind(&key).map(|(_, k)| k)
+        );
+
+        let popped = matcher.pop_override(&key).expect("override to pop");
+        assert_eq!("^yo", popped.pattern.to_string());
+
+        let it = utils::Words::new("hi there");
+        assert!(matcher.resolve("#test", Some("hi"), &it).is_some());
+
+        assert!(matcher.pop_override(&key).is_some());
+        assert!(matcher.pop_override(&key).is_none());
+        assert_eq!(
+            Some(MatchKind::Name),
+            matcher.get_with_kind(&key).map(|(_, k)| k)
+        );
+
+        let other = Key::new("#test", "new");
+        let (_, value) = entry("#test", "new", None);
+        matcher.push_override(other.clone(), value);
+        assert!(matcher.get(&other).is_some());
+        assert!(matcher.pop_override(&other).is_some());
+        assert!(matcher.get(&other).is_none());
+
+        let expires_at = Instant::now() + Duration::from_secs(60);
+        let (key, value) = entry("#test", "temporary", None);
+        matcher.insert_expiring(key.clone(), value, expires_at);
+
+        let (_, value) = entry("#test", "temporary", Some("^temp"));
+        matcher.push_override(key.clone(), value);
+        assert!(matcher.sweep_expired(expires_at).is_empty());
+
+        assert!(matcher.pop_override(&key).is_some());
+        assert_eq!(vec![key.clone()], matcher.sweep_expired(expires_at));
+        assert!(matcher.get(&key).is_none());
+    }
+
+    #[test]
+    fn test_resolve_params() {
+        let mut matcher = Matcher::new();
+
+        let (key, value) = entry("#test", "give", Some(r"^!give (\w+) (\d+)"));
+        let mut value = (*value).clone();
+        value.params = vec![String::from("user"), String::from("amount")];
+        matcher.insert(key.clone(), Arc::new(value));
+
+        let (other, value) = entry("#test", "take", Some(r"^!take (\w+)"));
+        matcher.insert(other.clone(), value);
+
+        let it = utils::Words::new("!give setbac 42");
+        let (entry, params) = matcher
+            .resolve_params("#test", Some("!give"), &it)
+            .expect("pattern to match");
+        assert_eq!(key, entry.key);
+        assert_eq!(2, params.len());
+        assert_eq!(Some(&"setbac"), params.get("user"));
+        assert_eq!(Some(&"42"), params.get("amount"));
+
+        let it = utils::Words::new("!take setbac");
+        let (entry, params) = matcher
+            .resolve_params("#test", Some("!take"), &it)
+            .expect("pattern to match");
+        assert_eq!(other, entry.key);
+        assert_eq!(Some(&"!take setbac"), params.get("0"));
+        assert_eq!(Some(&"setbac"), params.get("1"));
+    }
+
+    #[test]
+    fn test_to_db_rows() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "hello", None),
+            entry("#test", "bye", Some("^bye")),
+            entry("#other", "hello", None),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        let (key, value) = entry("#test", "word", None);
+        let mut value = (*value).clone();
+        value.pattern = Pattern::keyword("word");
+        matcher.insert(key, Arc::new(value));
+
+        let rows = matcher.to_db_rows();
+
+        assert_eq!(
+            vec![
+                (String::from("#other"), String::from("hello"), None),
+                (
+                    String::from("#test"),
+                    String::from("bye"),
+                    Some(String::from("^bye"))
+                ),
+                (String::from("#test"), String::from("hello"), None),
+            ],
+            rows
+        );
+
+        for (_, _, source) in rows {
+            let pattern = Pattern::from_db(source.as_deref()).expect("valid pattern");
+            assert_eq!(Some(source.as_deref()), pattern.to_db());
+        }
+    }
+
+    #[test]
+    fn test_shadowed() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "hello", None),
+            entry("#test", "greeting", Some("^hello")),
+            entry("#test", "wave", Some(r"^Hello\s+world")),
+            entry("#test", "help", Some("^helpers")),
+            entry("#test", "bye", Some("^bye")),
+            entry("#other", "hi", Some("^hello")),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        let key = Key::new("#test", "title");
+        let mut value = (*entry("#test", "title", None).1).clone();
+        value.pattern = Pattern::NameWithArgs {
+            name: String::from("!title"),
+        };
+        matcher.insert(key, Arc::new(value));
+
+        let (key, value) = entry("#test", "set-title", Some("^!title .+"));
+        matcher.insert(key, value);
+
+        assert_eq!(
+            vec![
+                (Key::new("#test", "greeting"), Key::new("#test", "hello")),
+                (Key::new("#test", "set-title"), Key::new("#test", "title")),
+                (Key::new("#test", "wave"), Key::new("#test", "hello")),
+            ],
+            matcher.shadowed()
+        );
+    }
+
+    #[test]
+    fn test_shadowed_partial_match() {
+        let mut matcher = Matcher::new();
+
+        for (key, value) in vec![
+            entry("#test", "hello", None),
+            entry("#test", "yell", Some("ell")),
+            entry("#test", "hell", Some("^hell")),
+            entry("#test", "greeting", Some("^(hello|hi)$")),
+        ] {
+            matcher.insert(key, value);
+        }
+
+        // NB: `ell` and `^hell` match part of the name, but they also match
+        // inputs like `yellow` and `hells` which aren't shadowed.
+        assert_eq!(
+            vec![(Key::new("#test", "greeting"), Key::new("#test", "hello"))],
+            matcher.shadowed()
+        );
     }
 }
@@ -21,7 +21,10 @@ use thiserror::Error;
 pub use self::after_streams::{AfterStream, AfterStreams};
 pub use self::aliases::{Alias, Aliases};
 pub use self::commands::{Command, Commands};
-pub use self::matcher::Captures;
+pub use self::matcher::{
+    Captures, Collision, Explanation, MatchKind, OwnedCaptures, RateLimit, ResolveError,
+    ResolveStrategy,
+};
 pub use self::promotions::{Promotion, Promotions};
 pub use self::script_storage::ScriptStorage;
 pub use self::themes::{Theme, Themes};
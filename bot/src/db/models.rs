@@ -42,6 +42,8 @@ pub struct Command {
     pub group: Option<String>,
     /// If the command is disabled.
     pub disabled: bool,
+    /// Seconds to wait before responding to the command, if any.
+    pub delay: Option<i32>,
 }
 
 #[derive(Debug, Clone, Default, diesel::AsChangeset)]
@@ -118,6 +118,7 @@ pub struct Promotions {
 
 impl Promotions {
     database_group_fns!(Promotion, Key);
+    database_rename_fn!(Promotion, Key);
 
     /// Construct a new promos store with a db.
     pub async fn load(db: db::Database) -> Result<Promotions, anyhow::Error> {
@@ -16,6 +16,7 @@ table! {
         text -> Text,
         group -> Nullable<Text>,
         disabled -> Bool,
+        delay -> Nullable<Integer>,
     }
 }
 
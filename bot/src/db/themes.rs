@@ -97,6 +97,7 @@ pub struct Themes {
 
 impl Themes {
     database_group_fns!(Theme, Key);
+    database_rename_fn!(Theme, Key);
 
     /// Construct a new commands store with a db.
     pub async fn load(db: db::Database) -> Result<Themes, anyhow::Error> {
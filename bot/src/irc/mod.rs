@@ -955,7 +955,7 @@ impl<'a> Handler<'a> {
         let first = it.next();
 
         if let Some(commands) = self.commands.as_ref() {
-            if let Some((command, captures)) = commands
+            if let Some((command, captures, delay)) = commands
                 .resolve(user.channel(), first.as_deref(), &it)
                 .await
             {
@@ -971,7 +971,26 @@ impl<'a> Handler<'a> {
                 };
 
                 let response = command.render(&vars)?;
-                self.sender.privmsg(response).await;
+
+                match delay {
+                    Some(delay) => {
+                        let sender = self.sender.clone();
+
+                        task::spawn(async move {
+                            tokio::time::sleep(delay).await;
+                            sender.privmsg(response).await;
+                        });
+                    }
+                    None => self.sender.privmsg(response).await,
+                }
+
+                self.global_bus
+                    .send(bus::Global::command_executed(
+                        user.channel(),
+                        &command.key.name,
+                        user.display_name().unwrap_or_default(),
+                    ))
+                    .await;
             }
         }
 
@@ -1139,8 +1158,13 @@ impl<'a> Handler<'a> {
                     }
                 }
                 "CLEARCHAT" => {
+                    // NB: the first argument is the channel, followed by the
+                    // user if only their messages are cleared.
+                    let channel = tail.first().map(String::as_str).unwrap_or_default();
+                    let user = tail.get(1);
+
                     if let Some(chat_log) = self.chat_log.as_ref() {
-                        match tail.first() {
+                        match user {
                             Some(user) => {
                                 chat_log.message_log.delete_by_user(user).await;
                             }
@@ -1149,6 +1173,18 @@ impl<'a> Handler<'a> {
                             }
                         }
                     }
+
+                    let tags = ClearChatTags::from_tags(m.tags);
+
+                    let event = match (user, tags.ban_duration) {
+                        (Some(user), Some(duration)) => {
+                            bus::Global::user_timeout(channel, user, duration)
+                        }
+                        (Some(user), None) => bus::Global::user_ban(channel, user),
+                        (None, _) => bus::Global::chat_clear(channel),
+                    };
+
+                    self.global_bus.send(event).await;
                 }
                 _ => {
                     log::trace!("Raw: {:?}", m);
@@ -1588,6 +1624,34 @@ impl ClearMsgTags {
     }
 }
 
+/// Tags associated with a CLEARCHAT.
+struct ClearChatTags {
+    /// Duration of a timeout in seconds. Absent if the user was banned.
+    ban_duration: Option<u64>,
+}
+
+impl ClearChatTags {
+    /// Extract tags from message.
+    #[allow(clippy::single_match)]
+    fn from_tags(tags: Option<Vec<Tag>>) -> ClearChatTags {
+        let mut ban_duration = None;
+
+        if let Some(tags) = tags {
+            for t in tags {
+                match t {
+                    Tag(name, Some(value)) => match name.as_str() {
+                        "ban-duration" => ban_duration = str::parse(&value).ok(),
+                        _ => (),
+                    },
+                    _ => (),
+                }
+            }
+        }
+
+        ClearChatTags { ban_duration }
+    }
+}
+
 #[derive(Debug)]
 pub enum SenderThreadItem {
     Exit,
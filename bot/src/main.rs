@@ -38,6 +38,14 @@ const FILE: &str = "file";
 #[cfg(not(feature = "windows"))]
 const STDOUT: &str = "stdout";
 const PACKAGE: &str = env!("CARGO_PKG_NAME");
+/// Messages on the global bus larger than this are not sent to clients, since
+/// they could hold up the connection.
+const GLOBAL_BUS_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+/// How long to wait before accepting web connections again after an error,
+/// unless configured.
+const WEB_ACCEPT_BACKOFF: time::Duration = time::Duration::from_secs(1);
+/// How often the health of the global bus is sent to overlays.
+const GLOBAL_BUS_HEALTH_INTERVAL: time::Duration = time::Duration::from_secs(5);
 
 #[cfg(feature = "windows")]
 mod internal {
@@ -487,8 +495,12 @@ async fn try_main(
 
     let message_bus = bus::Bus::new();
     injector.update(message_bus.clone()).await;
-    let global_bus = bus::Bus::new();
+    let global_bus = bus::Bus::builder()
+        .max_message_size(GLOBAL_BUS_MAX_MESSAGE_SIZE)
+        .build();
     injector.update(global_bus.clone()).await;
+    // NB: the task ends when the bus is shut down.
+    let health = global_bus.spawn_health(GLOBAL_BUS_HEALTH_INTERVAL);
     let youtube_bus = bus::Bus::new();
     injector.update(youtube_bus.clone()).await;
     let command_bus = bus::Bus::new();
@@ -512,8 +524,29 @@ async fn try_main(
         .build();
     injector.update(message_log.clone()).await;
 
+    let keepalive = settings
+        .get::<utils::Duration>("web/tcp-keepalive")
+        .await?
+        .map(|d| d.as_std());
+
+    let accept_backoff = settings
+        .get::<utils::Duration>("web/accept-backoff")
+        .await?
+        .map(|d| d.as_std())
+        .unwrap_or(WEB_ACCEPT_BACKOFF);
+
+    let addr = settings
+        .get::<String>("web/address")
+        .await?
+        .unwrap_or_else(|| String::from(web::ADDR));
+
+    let addr = str::parse(&addr).with_context(|| anyhow!("bad web address: {}", addr))?;
+
     let (web, future) = web::setup(
         &injector,
+        addr,
+        keepalive,
+        accept_backoff,
         message_log.clone(),
         message_bus.clone(),
         global_bus.clone(),
@@ -532,17 +565,20 @@ async fn try_main(
         .instrument(trace_span!(target: "futures", "web")),
     ));
 
+    let url = web.url();
+    system.set_url(url.clone());
+
     if settings.get::<bool>("first-run").await?.unwrap_or(true) {
-        log::info!("Opening {} for the first time", web::URL);
+        log::info!("Opening {} for the first time", url);
 
-        if let Err(e) = webbrowser::open(web::URL) {
+        if let Err(e) = webbrowser::open(&url) {
             log::error!("failed to open browser: {}", e);
         }
 
         settings.set("first-run", false).await?;
     }
 
-    log::info!("Listening on: {}", web::URL);
+    log::info!("Listening on: {}", url);
 
     let token_settings = settings.scoped("secrets/oauth2");
 
@@ -717,7 +753,8 @@ async fn try_main(
 
     let (stream_state_tx, stream_state_rx) = mpsc::channel(64);
 
-    let notify_after_streams = notify_after_streams(&injector, stream_state_rx, system.clone());
+    let notify_after_streams =
+        notify_after_streams(&injector, stream_state_rx, system.clone(), url);
     futures.push(Box::pin(
         notify_after_streams.instrument(trace_span!(target: "futures", "notify-after-streams",)),
     ));
@@ -733,7 +770,7 @@ async fn try_main(
         irc.run().instrument(trace_span!(target: "futures", "irc",)),
     ));
 
-    tokio::select! {
+    let intent = tokio::select! {
         Some(result) = futures.next() => {
             result.map(|_| Intent::Shutdown)
         }
@@ -753,16 +790,28 @@ async fn try_main(
             log::info!("shutdown triggered by signal");
             Ok(Intent::Shutdown)
         },
+    };
+
+    message_bus.shutdown();
+    global_bus.shutdown();
+    youtube_bus.shutdown();
+    command_bus.shutdown();
+
+    if let Err(e) = health.await {
+        oxidize::log_error!(e, "Global bus health task failed");
     }
+
+    intent
 }
 
 /// Notify if there are any after streams.
 ///
-/// If this is clicked, open the after-streams page.
+/// If this is clicked, open the after-streams page of the web server at `url`.
 async fn notify_after_streams(
     injector: &Injector,
     mut rx: mpsc::Receiver<stream_info::StreamState>,
     system: sys::System,
+    url: String,
 ) -> Result<()> {
     let (mut after_streams_stream, mut after_streams) = injector.stream::<db::AfterStreams>().await;
 
@@ -790,8 +839,10 @@ async fn notify_after_streams(
                                 list.len()
                             ));
 
-                            let reminder = reminder.on_click(|| {
-                                webbrowser::open(&format!("{}/after-streams", web::URL))?;
+                            let url = format!("{}/after-streams", url);
+
+                            let reminder = reminder.on_click(move || {
+                                webbrowser::open(&url)?;
                                 Ok(())
                             });
 
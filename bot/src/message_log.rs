@@ -28,9 +28,9 @@ pub enum Event {
 
 impl bus::Message for Event {
     /// The ID of a bussed message.
-    fn id(&self) -> Option<&'static str> {
+    fn id(&self) -> Option<bus::MessageId> {
         match *self {
-            Event::Enabled { .. } => Some("enabled"),
+            Event::Enabled { .. } => Some(bus::MessageId::Enabled),
             _ => None,
         }
     }
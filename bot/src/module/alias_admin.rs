@@ -37,7 +37,7 @@ impl command::Handler for Handler {
 
                 let pattern = match ctx.rest() {
                     pattern if pattern.trim().is_empty() => None,
-                    pattern => match regex::Regex::new(pattern) {
+                    pattern => match db::Pattern::compile(pattern) {
                         Ok(pattern) => Some(pattern),
                         Err(e) => {
                             ctx.user
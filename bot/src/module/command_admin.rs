@@ -3,6 +3,7 @@ use crate::command;
 use crate::db;
 use crate::module;
 use crate::prelude::*;
+use std::time;
 
 pub struct Handler {
     pub enabled: settings::Var<bool>,
@@ -44,7 +45,7 @@ impl command::Handler for Handler {
 
                 let pattern = match ctx.rest() {
                     pattern if pattern.trim().is_empty() => None,
-                    pattern => match regex::Regex::new(pattern) {
+                    pattern => match db::Pattern::compile(pattern) {
                         Ok(pattern) => Some(pattern),
                         Err(e) => {
                             ctx.user
@@ -55,13 +56,90 @@ impl command::Handler for Handler {
                     },
                 };
 
+                if let Some(pattern) = &pattern {
+                    let key = db::Key::new(ctx.channel(), &name);
+
+                    if commands
+                        .contains_regex_except(ctx.channel(), &key, pattern.as_str())
+                        .await
+                    {
+                        respond!(ctx, "Another command already uses that pattern.");
+                        return Ok(());
+                    }
+                }
+
                 if !commands.edit_pattern(ctx.channel(), &name, pattern).await? {
-                    respond!(ctx, format!("No such command: `{}`", name));
+                    respond!(ctx, no_such_command(&commands, ctx.channel(), &name).await);
                     return Ok(());
                 }
 
                 respond!(ctx, "Edited pattern for command.");
             }
+            Some("delay") => {
+                ctx.check_scope(auth::Scope::CommandEdit).await?;
+
+                let name = ctx.next_str("<name> [duration]")?;
+
+                let delay = match ctx.rest() {
+                    delay if delay.trim().is_empty() => None,
+                    delay => match delay.trim().parse::<utils::Duration>() {
+                        Ok(delay) => Some(delay),
+                        Err(e) => {
+                            ctx.user
+                                .respond(format!("Bad duration provided: {}", e))
+                                .await;
+                            return Ok(());
+                        }
+                    },
+                };
+
+                if !commands.edit_delay(ctx.channel(), &name, delay).await? {
+                    respond!(ctx, no_such_command(&commands, ctx.channel(), &name).await);
+                    return Ok(());
+                }
+
+                respond!(ctx, "Edited delay for command.");
+            }
+            Some("expire") => {
+                ctx.check_scope(auth::Scope::CommandEdit).await?;
+
+                let name = ctx.next_str("<name> <duration>")?;
+                let duration: utils::Duration = ctx.next_parse("<name> <duration>")?;
+                let expires_at = time::Instant::now() + duration.as_std();
+
+                if !commands.expire(ctx.channel(), &name, expires_at).await {
+                    respond!(ctx, no_such_command(&commands, ctx.channel(), &name).await);
+                    return Ok(());
+                }
+
+                respond!(ctx, format!("Command will be removed in {}.", duration));
+            }
+            Some("test") => {
+                ctx.check_scope(auth::Scope::CommandEdit).await?;
+
+                let mut it = utils::Words::new(Arc::new(ctx.rest().to_string()));
+                let first = it.next();
+
+                let explanation = commands.explain(ctx.channel(), first.as_deref(), &it).await;
+
+                let (key, pattern) = match (&explanation.key, &explanation.pattern) {
+                    (Some(key), Some(pattern)) => (key, pattern),
+                    _ => {
+                        respond!(ctx, "No command would match.");
+                        return Ok(());
+                    }
+                };
+
+                let captures = serde_json::to_string(&explanation.captures)?;
+
+                respond!(
+                    ctx,
+                    format!(
+                        "Would run `{}` (pattern: {}) with captures: {}",
+                        key.name, pattern, captures
+                    )
+                );
+            }
             None | Some(..) => {
                 respond!(
                     ctx,
@@ -74,6 +152,22 @@ impl command::Handler for Handler {
     }
 }
 
+/// Build a response for a command which doesn't exist, suggesting the
+/// commands with the closest names.
+async fn no_such_command(commands: &db::Commands, channel: &str, name: &str) -> String {
+    let suggestions = commands.suggest(channel, name, 3).await;
+
+    if suggestions.is_empty() {
+        return format!("No such command: `{}`", name);
+    }
+
+    format!(
+        "No such command: `{}`, did you mean: {}?",
+        name,
+        suggestions.join(", ")
+    )
+}
+
 pub struct Module;
 
 #[async_trait]
@@ -87,6 +181,7 @@ impl super::Module for Module {
         module::HookContext {
             injector,
             handlers,
+            futures,
             settings,
             ..
         }: module::HookContext<'_>,
@@ -94,6 +189,38 @@ impl super::Module for Module {
         let enabled = settings.var("command/enabled", true).await?;
         let commands = injector.var().await;
         handlers.insert("command", Handler { enabled, commands });
+
+        let (mut commands_stream, mut commands) = injector.stream::<db::Commands>().await;
+        let mut interval = tokio::time::interval(time::Duration::from_secs(60));
+
+        let future = async move {
+            loop {
+                tokio::select! {
+                    update = commands_stream.recv() => {
+                        commands = update;
+                    }
+                    _ = interval.tick() => {
+                        let commands = match commands.as_ref() {
+                            Some(commands) => commands,
+                            None => continue,
+                        };
+
+                        match commands.sweep_expired().await {
+                            Ok(expired) => {
+                                for key in expired {
+                                    log::info!("removed expired command: {}", key);
+                                }
+                            }
+                            Err(e) => {
+                                log_error!(e, "failed to remove expired commands");
+                            }
+                        }
+                    }
+                }
+            }
+        };
+
+        futures.push(Box::pin(future));
         Ok(())
     }
 }
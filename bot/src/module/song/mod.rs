@@ -511,7 +511,8 @@ impl module::Module for Module {
         let youtube = Constraint::build(&mut settings.scoped("youtube"), false, 60).await?;
 
         let help_cooldown = Cooldown::from_duration(Duration::seconds(5));
-        let requester = requester::SongRequester::new(request_reward, spotify, youtube);
+        let requester =
+            requester::SongRequester::new(request_reward, spotify, youtube, injector.var().await);
 
         handlers.insert(
             "song",
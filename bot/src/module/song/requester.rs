@@ -1,8 +1,10 @@
 use crate::auth::Scope;
+use crate::bus;
 use crate::currency::Currency;
 use crate::irc::RealUser;
 use crate::module::song::Constraint;
 use crate::player::{AddTrackError, Player};
+use crate::prelude::*;
 use crate::settings;
 use crate::track_id::{self, TrackId};
 use anyhow::Result;
@@ -21,6 +23,7 @@ pub(crate) struct SongRequester {
     request_reward: settings::Var<u32>,
     spotify: Constraint,
     youtube: Constraint,
+    global_bus: injector::Ref<bus::Bus<bus::Global>>,
 }
 
 impl SongRequester {
@@ -29,15 +32,19 @@ impl SongRequester {
         request_reward: settings::Var<u32>,
         spotify: Constraint,
         youtube: Constraint,
+        global_bus: injector::Ref<bus::Bus<bus::Global>>,
     ) -> Self {
         Self {
             request_reward,
             spotify,
             youtube,
+            global_bus,
         }
     }
 
     /// Perform the given song request.
+    ///
+    /// Whether the request was accepted or not is sent on the global bus.
     pub(crate) async fn request(
         &self,
         q: &str,
@@ -47,14 +54,33 @@ impl SongRequester {
         currency: RequestCurrency<'_>,
         player: &Player,
     ) -> Result<RequestOutcome, RequestError> {
+        let (track_id, result) = match self.find_track(q, player).await {
+            Ok(track_id) => {
+                let result = self
+                    .request_track(track_id.clone(), channel, user, real_user, currency, player)
+                    .await;
+
+                (Some(track_id), result)
+            }
+            Err(e) => (None, Err(e)),
+        };
+
+        if let Some(global_bus) = self.global_bus.load().await {
+            let reason = result.as_ref().err().map(|e| e.to_string());
+            global_bus
+                .send(bus::Global::song_request(user, track_id, reason))
+                .await;
+        }
+
+        result
+    }
+
+    /// Find the track matching the given request.
+    async fn find_track(&self, q: &str, player: &Player) -> Result<TrackId, RequestError> {
         if q.is_empty() {
             return Err(RequestError::BadRequest(None));
         }
 
-        let request_reward = self.request_reward.load().await;
-        let spotify = self.spotify.clone();
-        let youtube = self.youtube.clone();
-
         let track_id = match TrackId::parse_with_urls(&q) {
             Ok(track_id) => Some(track_id),
             Err(e) => {
@@ -79,12 +105,25 @@ impl SongRequester {
             None => player.search_track(q).await.map_err(RequestError::Error)?,
         };
 
-        let track_id = match track_id {
-            Some(track_id) => track_id,
-            None => {
-                return Err(RequestError::NoMatchingSong);
-            }
-        };
+        match track_id {
+            Some(track_id) => Ok(track_id),
+            None => Err(RequestError::NoMatchingSong),
+        }
+    }
+
+    /// Request the given track.
+    async fn request_track(
+        &self,
+        track_id: TrackId,
+        channel: &str,
+        user: &str,
+        real_user: Option<&RealUser<'_>>,
+        currency: RequestCurrency<'_>,
+        player: &Player,
+    ) -> Result<RequestOutcome, RequestError> {
+        let request_reward = self.request_reward.load().await;
+        let spotify = self.spotify.clone();
+        let youtube = self.youtube.clone();
 
         let (what, scope, enabled) = match track_id {
             TrackId::Spotify(..) => {
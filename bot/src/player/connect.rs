@@ -1,7 +1,8 @@
 use crate::api;
+use crate::bus;
 use crate::player;
 use crate::prelude::*;
-use crate::track_id::SpotifyId;
+use crate::track_id::{SpotifyId, TrackId};
 use anyhow::{bail, Error, Result};
 use std::sync::Arc;
 use std::time::Duration;
@@ -10,6 +11,7 @@ use thiserror::Error;
 /// Setup a player.
 pub(super) async fn setup(
     spotify: Arc<api::Spotify>,
+    global_bus: bus::Bus<bus::Global>,
     settings: crate::Settings,
 ) -> Result<(
     ConnectStream,
@@ -35,6 +37,7 @@ pub(super) async fn setup(
 
     let player = ConnectPlayer {
         spotify: spotify.clone(),
+        global_bus,
         device: device.clone(),
         settings: settings.clone(),
         volume_scale: volume_scale.clone(),
@@ -56,6 +59,13 @@ pub(super) async fn setup(
         loop {
             tokio::select! {
                 update = device_stream.recv() => {
+                    if let Some(update) = &update {
+                        player
+                            .global_bus
+                            .send(bus::Global::device_changed(update, Some(scaled_volume)))
+                            .await;
+                    }
+
                     *device.write().await = update;
 
                     if config_tx.send(ConfigurationEvent::DeviceChanged).is_err() {
@@ -100,6 +110,8 @@ impl ConnectError {
 #[derive(Clone)]
 pub(super) struct ConnectPlayer {
     spotify: Arc<api::Spotify>,
+    /// Bus to notify about volume changes.
+    global_bus: bus::Bus<bus::Global>,
     /// Currently configured device.
     device: settings::Var<Option<String>>,
     /// Access to settings.
@@ -123,7 +135,16 @@ impl ConnectPlayer {
             .me_player_play(device_id.as_deref(), track_uri.as_deref(), elapsed)
             .await;
 
-        warn_on_error(ConnectError::handle(result, "play"));
+        let result = ConnectError::handle(result, "play");
+
+        if let Err(e) = &result {
+            let track_id = id.map(TrackId::Spotify);
+            self.global_bus
+                .send(bus::Global::playback_error(track_id, e))
+                .await;
+        }
+
+        warn_on_error(result);
     }
 
     /// Play the next song.
@@ -196,15 +217,22 @@ impl ConnectPlayer {
         update
     }
 
+    /// Update the volume of the device, notifying about the new volume once
+    /// it has been applied.
     async fn volume_update(&self, volume: u32) -> Result<(), ConnectError> {
-        let volume = (volume as f32) / 100f32;
         let device_id = self.device.load().await;
+
         ConnectError::handle(
             self.spotify
-                .me_player_volume(device_id.as_deref(), volume)
+                .me_player_volume(device_id.as_deref(), (volume as f32) / 100f32)
                 .await,
             "volume",
-        )
+        )?;
+
+        self.global_bus
+            .send(bus::Global::volume("spotify", volume))
+            .await;
+        Ok(())
     }
 }
 
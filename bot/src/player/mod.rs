@@ -181,15 +181,19 @@ pub async fn run(
 
     let mut futures = utils::Futures::default();
 
-    let (connect_stream, connect_player, device, future) =
-        self::connect::setup(spotify.clone(), settings.scoped("spotify")).await?;
+    let (connect_stream, connect_player, device, future) = self::connect::setup(
+        spotify.clone(),
+        global_bus.clone(),
+        settings.scoped("spotify"),
+    )
+    .await?;
 
     futures.push(Box::pin(
         future.instrument(trace_span!(target: "futures", "spotify")),
     ));
 
     let (youtube_player, future) =
-        self::youtube::setup(youtube_bus, settings.scoped("youtube")).await?;
+        self::youtube::setup(youtube_bus, global_bus.clone(), settings.scoped("youtube")).await?;
 
     futures.push(Box::pin(
         future.instrument(trace_span!(target: "futures", "youtube")),
@@ -213,6 +217,17 @@ pub async fn run(
         Fuse::new(tokio::time::interval(song_update_interval.as_std()))
     };
 
+    let (paused_update_interval_stream, paused_update_interval) = settings
+        .stream("paused-update-interval")
+        .or_default()
+        .await?;
+
+    let paused_update_interval = if paused_update_interval.is_empty() {
+        Fuse::empty()
+    } else {
+        Fuse::new(tokio::time::interval(paused_update_interval.as_std()))
+    };
+
     let (detached_stream, detached) = settings.stream("detached").or_default().await?;
 
     let duplicate_duration = settings
@@ -260,6 +275,8 @@ pub async fn run(
         detached_stream,
         song_update_interval,
         song_update_interval_stream,
+        paused_update_interval,
+        paused_update_interval_stream,
     };
 
     futures.push(Box::pin(
@@ -19,6 +19,11 @@ pub(super) struct PlaybackFuture {
     pub(super) song_update_interval: Fuse<tokio::time::Interval>,
     /// Stream for when song update interval is updated.
     pub(super) song_update_interval_stream: settings::Stream<utils::Duration>,
+    /// Optional stream indicating that we want to send the state of a paused
+    /// song on the global bus.
+    pub(super) paused_update_interval: Fuse<tokio::time::Interval>,
+    /// Stream for when paused update interval is updated.
+    pub(super) paused_update_interval_stream: settings::Stream<utils::Duration>,
 }
 
 impl PlaybackFuture {
@@ -52,6 +57,7 @@ impl PlaybackFuture {
         tokio::pin!(song_timeout);
 
         let mut song_update_interval = self.song_update_interval;
+        let mut paused_update_interval = self.paused_update_interval;
 
         loop {
             tokio::select! {
@@ -81,6 +87,16 @@ impl PlaybackFuture {
                 _ = song_update_interval.as_pin_mut().poll_inner(|mut i, cx| i.poll_tick(cx)) => {
                     self.internal.write().await.song_update().await;
                 }
+                value = self.paused_update_interval_stream.recv() => {
+                    paused_update_interval = if value.is_empty() {
+                        Fuse::empty()
+                    } else {
+                        Fuse::new(tokio::time::interval(value.as_std()))
+                    };
+                }
+                _ = paused_update_interval.as_pin_mut().poll_inner(|mut i, cx| i.poll_tick(cx)) => {
+                    self.internal.read().await.paused_update().await;
+                }
                 event = self.connect_stream.recv() => {
                     self.internal.write().await.handle_player_event(event).await?;
                 }
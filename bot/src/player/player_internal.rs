@@ -130,10 +130,26 @@ impl PlayerInternal {
         Ok(())
     }
 
+    /// Notify that the current song was paused or resumed.
+    ///
+    /// Only the playback state is sent, but the full song is cached for
+    /// clients which connect later. `SongModified` is still sent since remote
+    /// listeners rely on it to pick up the change.
+    async fn notify_song_state(&self, song: &Song) -> Result<()> {
+        let full = bus::Global::song(Some(song))?;
+
+        self.global_bus
+            .send_delta(bus::Global::song_state_changed(song), full)
+            .await;
+        self.global_bus.send(bus::Global::SongModified).await;
+        Ok(())
+    }
+
     /// Notify a change in the current song.
     async fn notify_song_change(&self, song: Option<&Song>) -> Result<()> {
-        self.global_bus.send(bus::Global::song(song)?).await;
-        self.global_bus.send(bus::Global::SongModified).await;
+        self.global_bus
+            .send_all(vec![bus::Global::song(song)?, bus::Global::SongModified])
+            .await;
         Ok(())
     }
 
@@ -193,6 +209,11 @@ impl PlayerInternal {
         if let Some(song) = song.as_mut() {
             song.pause();
             self.switch_current_player(song.player()).await?;
+
+            // Cue the video so that it's ready by the time it's played.
+            if let TrackId::YouTube(id) = &song.item.track_id {
+                self.youtube_player.load(id).await;
+            }
         } else {
             self.switch_current_player(PlayerKind::None).await?;
         }
@@ -229,7 +250,7 @@ impl PlayerInternal {
     async fn resume_song(&mut self, source: Source, song: Song) -> Result<()> {
         self.send_play_command(&song).await;
         self.switch_current_player(song.player()).await?;
-        self.notify_song_change(Some(&song)).await?;
+        self.notify_song_state(&song).await?;
 
         if let Source::Manual = source {
             let feedback = self.song_switch_feedback.load().await;
@@ -352,7 +373,10 @@ impl PlayerInternal {
                     self.bus.send_sync(Event::Pausing);
                 }
 
-                self.notify_song_change(song.as_ref()).await?;
+                match &song {
+                    Some(song) => self.notify_song_state(song).await?,
+                    None => self.notify_song_change(None).await?,
+                }
             }
             PlaybackMode::Queue => {
                 self.connect_player.pause().await;
@@ -704,6 +728,18 @@ impl PlayerInternal {
         }
     }
 
+    /// Handle a paused song update, sending the frozen state of the current
+    /// song if it is paused.
+    pub(super) async fn paused_update(&self) {
+        if let State::Paused = self.injector.get::<State>().await.unwrap_or_default() {
+            if let Some(song) = self.injector.get::<Song>().await {
+                self.global_bus
+                    .send(bus::Global::song_state_changed(&song))
+                    .await;
+            }
+        }
+    }
+
     /// Update the detached state.
     pub(super) async fn update_detached(&mut self, detached: bool) -> Result<()> {
         if detached {
@@ -36,6 +36,26 @@ impl Track {
         }
     }
 
+    /// Get the URL of the largest album art of the track, or the largest
+    /// thumbnail for YouTube videos.
+    pub fn art_url(&self) -> Option<&str> {
+        match *self {
+            Self::Spotify { ref track } => track
+                .album
+                .images
+                .iter()
+                .max_by_key(|i| i.width.unwrap_or_default())
+                .map(|i| i.url.as_str()),
+            Self::YouTube { ref video } => video
+                .snippet
+                .as_ref()?
+                .thumbnails
+                .values()
+                .max_by_key(|t| t.width)
+                .map(|t| t.url.as_str()),
+        }
+    }
+
     /// Convert into JSON.
     /// TODO: this is a hack to avoid breaking web API.
     pub fn to_json(&self) -> Result<serde_json::Value> {
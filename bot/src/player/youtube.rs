@@ -7,6 +7,7 @@ use std::time::Duration;
 /// Setup a player.
 pub(super) async fn setup(
     bus: bus::Bus<bus::YouTube>,
+    global_bus: bus::Bus<bus::Global>,
     settings: crate::Settings,
 ) -> Result<(YouTubePlayer, impl Future<Output = Result<()>>)> {
     let (mut volume_scale_stream, mut volume_scale) =
@@ -17,6 +18,7 @@ pub(super) async fn setup(
 
     let player = YouTubePlayer {
         bus,
+        global_bus,
         settings,
         volume: volume.clone(),
     };
@@ -48,6 +50,7 @@ pub(super) async fn setup(
 #[derive(Clone)]
 pub(super) struct YouTubePlayer {
     bus: bus::Bus<bus::YouTube>,
+    global_bus: bus::Bus<bus::Global>,
     settings: crate::Settings,
     volume: settings::Var<u32>,
 }
@@ -74,6 +77,11 @@ impl YouTubePlayer {
         self.bus.send(bus::YouTube::YouTubeCurrent { event }).await;
     }
 
+    /// Load the given video without playing it.
+    pub(super) async fn load(&self, video_id: &str) {
+        self.bus.send(bus::YouTube::load(video_id)).await;
+    }
+
     pub(super) async fn pause(&self) {
         let event = bus::YouTubeEvent::Pause;
         self.bus.send(bus::YouTube::YouTubeCurrent { event }).await;
@@ -103,5 +111,8 @@ impl YouTubePlayer {
 
     async fn volume_update(&self, volume: u32) {
         self.bus.send(bus::YouTube::YouTubeVolume { volume }).await;
+        self.global_bus
+            .send(bus::Global::volume("youtube", volume))
+            .await;
     }
 }
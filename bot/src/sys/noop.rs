@@ -21,6 +21,8 @@ impl System {
 
     pub fn notification(&self, _: Notification) {}
 
+    pub fn set_url(&self, _url: String) {}
+
     pub fn join(&self) -> Result<(), Error> {
         Ok(())
     }
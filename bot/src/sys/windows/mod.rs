@@ -1,6 +1,5 @@
 use crate::prelude::*;
 use crate::sys::Notification;
-use crate::web;
 use anyhow::{anyhow, bail, Context as _, Error};
 use parking_lot::Mutex;
 use std::collections::VecDeque;
@@ -25,6 +24,7 @@ pub enum Event {
     Cleared,
     Errored(String),
     Notification(Notification),
+    Url(String),
 }
 
 #[derive(Clone)]
@@ -67,6 +67,13 @@ impl System {
         }
     }
 
+    /// Set the URL of the web server, which is opened from the menu.
+    pub fn set_url(&self, url: String) {
+        if let Err(e) = self.events.send(Event::Url(url)) {
+            log::error!("failed to send url: {}", e);
+        }
+    }
+
     /// Join the current thread.
     pub fn join(&self) -> Result<(), Error> {
         let _ = self.shutdown.send(());
@@ -173,6 +180,7 @@ pub fn setup(root: &Path, log_file: &Path) -> Result<System, Error> {
         window.add_menu_entry(6, "Exit", false)?;
 
         let mut notification_on_click = VecDeque::new();
+        let mut url = None;
 
         loop {
             tokio::select! {
@@ -196,13 +204,18 @@ pub fn setup(root: &Path, log_file: &Path) -> Result<System, Error> {
                             window.send_notification(n)
                             .context("sending notification")?;
                         }
+                        Event::Url(new_url) => {
+                            url = Some(new_url);
+                        }
                     }
                 }
                 e = window.tick() => {
                     match e {
                         window::Event::MenuClicked(idx) => match idx {
                             0 => {
-                                let _ = webbrowser::open(web::URL)?;
+                                if let Some(url) = &url {
+                                    let _ = webbrowser::open(url)?;
+                                }
                             }
                             2 => {
                                 let _ = open_dir(&log_file)?;
@@ -24,10 +24,13 @@ use warp::{body, filters, path, Filter as _};
 mod cache;
 mod chat;
 mod settings;
+mod sse;
+mod ws;
 
-use self::{cache::Cache, chat::Chat, settings::Settings};
+use self::{cache::Cache, chat::Chat, settings::Settings, sse::sse_bus, ws::send_bus};
 
-pub const URL: &str = "http://localhost:12345";
+/// The default address to bind the web server to.
+pub const ADDR: &str = "0.0.0.0:12345";
 
 mod assets {
     #[derive(rust_embed::RustEmbed)]
@@ -588,6 +591,8 @@ struct Auth {
     active_connections: Arc<RwLock<HashMap<String, ConnectionMeta>>>,
     auth: auth::Auth,
     settings: injector::Ref<crate::Settings>,
+    /// The URL the web server can be reached at.
+    url: String,
 }
 
 #[derive(serde::Deserialize)]
@@ -601,11 +606,13 @@ impl Auth {
         auth: auth::Auth,
         active_connections: Arc<RwLock<HashMap<String, ConnectionMeta>>>,
         settings: injector::Ref<crate::Settings>,
+        url: String,
     ) -> filters::BoxedFilter<(impl warp::Reply,)> {
         let api = Auth {
             auth,
             active_connections,
             settings,
+            url,
         };
 
         let route = warp::get()
@@ -764,7 +771,7 @@ impl Auth {
             settings.set("remote/secret-key", key.as_str()).await?;
         }
 
-        let mut parts = URL.parse::<warp::http::Uri>()?.into_parts();
+        let mut parts = self.url.parse::<warp::http::Uri>()?.into_parts();
         parts.path_and_query = Some(warp::http::uri::PathAndQuery::from_static(
             "?received-key=true",
         ));
@@ -971,8 +978,19 @@ impl Api {
 }
 
 /// Set up the web endpoint.
+///
+/// The web server binds to `addr`, which may use port 0 to bind to any free
+/// port. The address actually bound is available through
+/// [Server::local_addr], and the URL to reach it through [Server::url].
+///
+/// Accepted connections have `TCP_NODELAY` set, and TCP keepalive enabled
+/// with the given interval if specified. Errors when accepting connections
+/// are logged, and accepting is retried after `accept_backoff`.
 pub async fn setup(
     injector: &Injector,
+    addr: SocketAddr,
+    keepalive: Option<std::time::Duration>,
+    accept_backoff: std::time::Duration,
     message_log: message_log::MessageLog,
     message_bus: bus::Bus<message_log::Event>,
     global_bus: bus::Bus<bus::Global>,
@@ -981,7 +999,8 @@ pub async fn setup(
     auth: auth::Auth,
     latest: crate::settings::Var<Option<api::github::Release>>,
 ) -> Result<(Server, impl Future<Output = ()>)> {
-    let addr: SocketAddr = str::parse("0.0.0.0:12345")?;
+    let mut incoming = hyper::server::conn::AddrIncoming::bind(&addr)?;
+    let local_addr = incoming.local_addr();
 
     let channel = injector
         .var_key(Key::<String>::tagged(tags::Globals::Channel)?)
@@ -1083,6 +1102,7 @@ pub async fn setup(
                 auth,
                 active_connections.clone(),
                 injector.var().await,
+                url_for(local_addr),
             ))
             .boxed());
         let route = route.or(Aliases::route(injector.var().await));
@@ -1112,6 +1132,24 @@ pub async fn setup(
         warp::path("api").and(route)
     };
 
+    let latest_messages =
+        warp::path!("latest" / "messages" / ..).and(latest_bus(message_bus.clone()));
+    let latest_overlay = warp::path!("latest" / "overlay" / ..).and(latest_bus(global_bus.clone()));
+    let latest_youtube =
+        warp::path!("latest" / "youtube" / ..).and(latest_bus(youtube_bus.clone()));
+
+    let sse_messages = warp::get()
+        .and(warp::path!("sse" / "messages"))
+        .and(sse_bus(message_bus.clone()));
+
+    let sse_overlay = warp::get()
+        .and(warp::path!("sse" / "overlay"))
+        .and(sse_bus(global_bus.clone()));
+
+    let sse_youtube = warp::get()
+        .and(warp::path!("sse" / "youtube"))
+        .and(sse_bus(youtube_bus.clone()));
+
     let ws_messages = warp::get()
         .and(warp::path!("ws" / "messages"))
         .and(send_bus(message_bus).recover(recover));
@@ -1125,6 +1163,12 @@ pub async fn setup(
         .and(send_bus(youtube_bus).recover(recover));
 
     let routes = api.recover(recover);
+    let routes = routes.or(latest_messages.recover(recover));
+    let routes = routes.or(latest_overlay.recover(recover));
+    let routes = routes.or(latest_youtube.recover(recover));
+    let routes = routes.or(sse_messages.recover(recover));
+    let routes = routes.or(sse_overlay.recover(recover));
+    let routes = routes.or(sse_youtube.recover(recover));
     let routes = routes.or(ws_messages.recover(recover));
     let routes = routes.or(ws_overlay.recover(recover));
     let routes = routes.or(ws_youtube.recover(recover));
@@ -1140,14 +1184,27 @@ pub async fn setup(
             }));
 
     let routes = routes.recover(recover);
-    let service = warp::serve(routes);
+    let service = warp::service(routes);
 
-    // TODO: fix when this review is fixed: https://github.com/seanmonstar/warp/pull/265#pullrequestreview-294644379
-    let server_future = service.try_bind_ephemeral(addr)?.1;
+    incoming.set_nodelay(true);
+    incoming.set_keepalive(keepalive);
+    // NB: accept errors are handled by `accept_with_backoff` instead.
+    incoming.set_sleep_on_errors(false);
+
+    let incoming = futures_util::stream::poll_fn(move |cx| {
+        use hyper::server::accept::Accept as _;
+        Pin::new(&mut incoming).poll_accept(cx)
+    });
+
+    let incoming = Box::pin(accept_with_backoff(incoming, accept_backoff));
+
+    let server_future = serve_incoming(service, incoming);
+    log::info!("web server listening on {}", local_addr);
 
     let server = Server {
         player,
         active_connections,
+        local_addr,
     };
 
     return Ok((server, server_future));
@@ -1175,6 +1232,119 @@ pub async fn setup(
     }
 }
 
+/// Skip errors when accepting connections, so that a transient error like
+/// running out of file descriptors doesn't stop the server.
+///
+/// Errors affecting a single connection are skipped immediately, while
+/// others are logged and accepting is retried after `backoff`.
+fn accept_with_backoff<S, T>(
+    mut incoming: S,
+    backoff: std::time::Duration,
+) -> impl stream::Stream<Item = std::io::Result<T>>
+where
+    S: stream::Stream<Item = std::io::Result<T>> + Unpin,
+{
+    use std::io::ErrorKind;
+
+    async_stream::stream! {
+        while let Some(result) = incoming.next().await {
+            let e = match result {
+                Ok(conn) => {
+                    yield Ok(conn);
+                    continue;
+                }
+                Err(e) => e,
+            };
+
+            match e.kind() {
+                ErrorKind::ConnectionRefused
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::ConnectionReset => {
+                    log::trace!("connection failed while being accepted: {}", e);
+                }
+                _ => {
+                    log::warn!("failed to accept connection, retrying in {:?}: {}", backoff, e);
+                    tokio::time::sleep(backoff).await;
+                }
+            }
+        }
+    }
+}
+
+/// The remote address of the connection a request arrived on.
+///
+/// Since connections are accepted outside of warp, it can't tell where they
+/// came from by itself. So this is inserted as a request extension by
+/// `serve_incoming` instead.
+#[derive(Debug, Clone, Copy)]
+struct RemoteAddr(SocketAddr);
+
+/// Get the URL a web server bound to `addr` can be reached at.
+///
+/// A server bound to an unspecified address like `0.0.0.0` is reached through
+/// `localhost`.
+fn url_for(addr: SocketAddr) -> String {
+    if addr.ip().is_unspecified() {
+        format!("http://localhost:{}", addr.port())
+    } else {
+        format!("http://{}", addr)
+    }
+}
+
+/// Extract the remote address of the connection, if it is known.
+///
+/// Use this instead of `warp::addr::remote`, which is always empty for
+/// connections served through `serve_incoming`.
+fn remote_addr() -> filters::BoxedFilter<(Option<SocketAddr>,)> {
+    warp::ext::optional::<RemoteAddr>()
+        .map(|addr: Option<RemoteAddr>| addr.map(|a| a.0))
+        .boxed()
+}
+
+/// Serve the given service over a stream of incoming connections.
+///
+/// The remote address of every connection is recorded in the extensions of
+/// the requests made over it, see `remote_addr`.
+fn serve_incoming<S, I>(service: S, incoming: I) -> impl Future<Output = ()>
+where
+    S: hyper::service::Service<
+            hyper::Request<hyper::Body>,
+            Response = hyper::Response<hyper::Body>,
+            Error = std::convert::Infallible,
+        > + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+    I: stream::Stream<Item = std::io::Result<hyper::server::conn::AddrStream>> + Send + 'static,
+{
+    use hyper::server::conn::AddrStream;
+    use hyper::service::Service as _;
+
+    let make_service = hyper::service::make_service_fn(move |conn: &AddrStream| {
+        let remote_addr = RemoteAddr(conn.remote_addr());
+        let service = service.clone();
+
+        async move {
+            let service =
+                hyper::service::service_fn(move |mut req: hyper::Request<hyper::Body>| {
+                    req.extensions_mut().insert(remote_addr);
+                    service.clone().call(req)
+                });
+
+            Ok::<_, std::convert::Infallible>(service)
+        }
+    });
+
+    let accept = hyper::server::accept::from_stream(incoming);
+    let server = hyper::Server::builder(accept).serve(make_service);
+
+    async move {
+        if let Err(e) = server.await {
+            log::error!("web server failed: {}", e);
+        }
+    }
+}
+
 pub struct Fragment {
     string: String,
 }
@@ -1263,9 +1433,23 @@ pub struct Server {
     player: injector::Ref<player::Player>,
     /// Callbacks for when we have received a token.
     active_connections: Arc<RwLock<HashMap<String, ConnectionMeta>>>,
+    /// The address the server is bound to.
+    local_addr: SocketAddr,
 }
 
 impl Server {
+    /// Get the address the server is bound to.
+    ///
+    /// This differs from the requested address when binding to port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Get the URL the server can be reached at.
+    pub fn url(&self) -> String {
+        url_for(self.local_addr)
+    }
+
     pub async fn update_connection(&self, id: &str, connection: ConnectionMeta) {
         self.active_connections
             .write()
@@ -1284,45 +1468,316 @@ pub struct ReceivedToken {
     pub state: String,
 }
 
-/// Connecting a bus to a websocket connection.
-fn send_bus<T>(bus: bus::Bus<T>) -> filters::BoxedFilter<(impl warp::Reply,)>
+/// Serve the latest messages of a bus over HTTP.
+///
+/// Responds with all cached messages, or with the single message cached
+/// under the key given by the rest of the path.
+fn latest_bus<T>(bus: bus::Bus<T>) -> filters::BoxedFilter<(impl warp::Reply,)>
 where
     T: bus::Message,
 {
-    warp::ws()
-        .map({
-            move |ws: warp::ws::Ws| {
-                let bus = bus.clone();
-
-                ws.on_upgrade(move |websocket: warp::filters::ws::WebSocket| async {
-                    if let Err(e) = send_bus_forward(bus, websocket).await {
-                        log_error!(e, "websocket error");
-                    }
-                })
+    warp::get()
+        .and(warp::path::tail())
+        .and_then(move |tail: path::Tail| {
+            let bus = bus.clone();
+
+            async move {
+                if tail.as_str().is_empty() {
+                    let latest = bus.latest().await;
+                    return Ok(warp::reply::json(&latest));
+                }
+
+                match bus.latest_by_key(tail.as_str()).await {
+                    Some(m) => Ok(warp::reply::json(&m)),
+                    None => Err(warp::reject::not_found()),
+                }
             }
         })
         .boxed()
 }
 
-/// Forward the bus message to the websocket.
-async fn send_bus_forward<T>(bus: bus::Bus<T>, mut ws: warp::filters::ws::WebSocket) -> Result<()>
-where
-    T: bus::Message,
-{
-    use futures_util::sink::SinkExt as _;
+#[cfg(test)]
+mod tests {
+    use super::accept_with_backoff;
+    use crate::bus;
+    use crate::prelude::*;
+    use std::io;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::tungstenite::{self, Message};
+
+    #[test]
+    fn test_accept_with_backoff() {
+        let incoming = stream::iter(vec![
+            Ok(1),
+            Err(io::Error::from_raw_os_error(24)),
+            Err(io::Error::from(io::ErrorKind::ConnectionReset)),
+            Ok(2),
+        ]);
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+
+        let accepted = runtime.block_on(async {
+            let incoming = accept_with_backoff(incoming, Duration::from_millis(1));
+            tokio::pin!(incoming);
+
+            let mut accepted = Vec::new();
+
+            while let Some(conn) = incoming.next().await {
+                accepted.push(conn.unwrap());
+            }
+
+            accepted
+        });
+
+        assert_eq!(vec![1, 2], accepted);
+    }
+
+    #[test]
+    fn test_url_for() {
+        assert_eq!(
+            "http://localhost:12345",
+            super::url_for(([0, 0, 0, 0], 12345).into())
+        );
+        assert_eq!(
+            "http://127.0.0.1:8080",
+            super::url_for(([127, 0, 0, 1], 8080).into())
+        );
+        assert_eq!(
+            "http://[::1]:8080",
+            super::url_for("[::1]:8080".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_connected_peers() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let bus = bus::Bus::<bus::Global>::new();
+            let addr = spawn_server(super::ws::send_bus(bus.clone()));
+
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            let peer = client.local_addr().unwrap();
+
+            client
+                .write_all(
+                    b"GET / HTTP/1.1\r\n\
+                    Host: localhost\r\n\
+                    Connection: Upgrade\r\n\
+                    Upgrade: websocket\r\n\
+                    Sec-WebSocket-Version: 13\r\n\
+                    Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                    \r\n",
+                )
+                .await
+                .unwrap();
+
+            let mut status = [0u8; 12];
+            client.read_exact(&mut status).await.unwrap();
+            assert_eq!(b"HTTP/1.1 101", &status);
+
+            // NB: the connection is registered once the upgraded socket runs.
+            for _ in 0..100 {
+                if !bus.connected_peers().is_empty() {
+                    break;
+                }
+
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+
+            assert_eq!(vec![peer], bus.connected_peers());
 
-    // add a receiver and forward all new messages.
-    let mut rx = bus.subscribe();
+            drop(client);
+
+            for _ in 0..100 {
+                if bus.connected_peers().is_empty() {
+                    break;
+                }
+
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
 
-    // send all cached messages.
-    for m in bus.latest().await {
-        let m = filters::ws::Message::text(serde_json::to_string(&m)?);
-        ws.send(m).await?;
+            assert!(bus.connected_peers().is_empty());
+        });
     }
 
-    loop {
-        let m = rx.recv().await?;
-        let m = filters::ws::Message::text(serde_json::to_string(&m)?);
-        ws.send(m).await?;
+    #[test]
+    fn test_send_bus_with_transform() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let bus = bus::Bus::<bus::Global>::new();
+
+            bus.send(bus::Global::SongProgress {
+                track_id: None,
+                elapsed: 1,
+                duration: 2,
+                duration_known: true,
+            })
+            .await;
+
+            let transform: super::ws::Transform<bus::Global> =
+                Arc::new(bus::Global::progress_in_millis);
+            let addr = spawn_server(super::ws::send_bus_with(bus.clone(), Some(transform)));
+
+            let mut client = connect(addr).await;
+
+            let connected = next_json(&mut client).await;
+            assert_eq!(Some("bus/connected"), connected["type"].as_str());
+
+            let replayed = next_json(&mut client).await;
+            assert_eq!(Some("song/progress"), replayed["type"].as_str());
+            assert_eq!(Some(1000), replayed["elapsed"].as_u64());
+            assert_eq!(Some(2000), replayed["duration"].as_u64());
+
+            bus.send(bus::Global::SongStateChanged {
+                is_playing: true,
+                elapsed: 3,
+            })
+            .await;
+
+            let live = next_json(&mut client).await;
+            assert_eq!(Some("song/state"), live["type"].as_str());
+            assert_eq!(Some(3000), live["elapsed"].as_u64());
+        });
+    }
+
+    #[test]
+    fn test_resume() {
+        use futures_util::sink::SinkExt as _;
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let bus = bus::Bus::<bus::Global>::new();
+            let addr = spawn_server(super::ws::send_bus(bus.clone()));
+
+            let mut client = connect(addr).await;
+            let connected = next_json(&mut client).await;
+            let token = connected["token"].as_str().unwrap().to_string();
+
+            bus.send(bus::Global::SongProgress {
+                track_id: None,
+                elapsed: 1,
+                duration: 2,
+                duration_known: true,
+            })
+            .await;
+
+            let live = next_json(&mut client).await;
+            assert_eq!(Some("song/progress"), live["type"].as_str());
+            let last_seq = live["seq"].as_u64().unwrap();
+            drop(client);
+
+            bus.send(bus::Global::SongStateChanged {
+                is_playing: true,
+                elapsed: 3,
+            })
+            .await;
+
+            let resume = |token: &str| {
+                let resume = serde_json::json!({
+                    "type": "resume",
+                    "token": token,
+                    "last_seq": last_seq,
+                });
+
+                Message::text(resume.to_string())
+            };
+
+            // only the missed message is replayed, without a snapshot.
+            let mut client = connect(addr).await;
+            client.send(resume(&token)).await.unwrap();
+
+            let connected = next_json(&mut client).await;
+            assert_eq!(Some("bus/connected"), connected["type"].as_str());
+
+            let replayed = next_json(&mut client).await;
+            assert_eq!(Some("song/state"), replayed["type"].as_str());
+            assert_eq!(Some(last_seq + 1), replayed["seq"].as_u64());
+
+            bus.send(bus::Global::SongStateChanged {
+                is_playing: false,
+                elapsed: 4,
+            })
+            .await;
+
+            let live = next_json(&mut client).await;
+            assert_eq!(Some("song/state"), live["type"].as_str());
+            assert_eq!(Some(last_seq + 2), live["seq"].as_u64());
+
+            // a resume which isn't possible gets a snapshot instead.
+            let mut client = connect(addr).await;
+            client.send(resume("bad-token")).await.unwrap();
+
+            let connected = next_json(&mut client).await;
+            assert_eq!(Some("bus/connected"), connected["type"].as_str());
+
+            let mut snapshot = Vec::new();
+
+            loop {
+                let m = next_json(&mut client).await;
+
+                if m["type"].as_str() == Some("bus/resynced") {
+                    break;
+                }
+
+                snapshot.push(m);
+            }
+
+            assert!(snapshot
+                .iter()
+                .any(|m| m["type"].as_str() == Some("song/progress")));
+        });
+    }
+
+    /// Connect a websocket client to a server spawned with [spawn_server].
+    async fn connect(addr: std::net::SocketAddr) -> tokio_tungstenite::WebSocketStream<TcpStream> {
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (client, _) = tokio_tungstenite::client_async("ws://localhost/", client)
+            .await
+            .unwrap();
+        client
+    }
+
+    /// Serve the given filter on a local port, returning its address.
+    fn spawn_server<R>(filter: warp::filters::BoxedFilter<(R,)>) -> std::net::SocketAddr
+    where
+        R: warp::Reply + Send + 'static,
+    {
+        let mut incoming =
+            hyper::server::conn::AddrIncoming::bind(&([127, 0, 0, 1], 0).into()).unwrap();
+        let addr = incoming.local_addr();
+
+        let incoming = futures_util::stream::poll_fn(move |cx| {
+            use hyper::server::accept::Accept as _;
+            Pin::new(&mut incoming).poll_accept(cx)
+        });
+
+        tokio::spawn(super::serve_incoming(warp::service(filter), incoming));
+        addr
+    }
+
+    /// Read the next text message from the websocket as JSON.
+    async fn next_json<S>(client: &mut S) -> serde_json::Value
+    where
+        S: stream::Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+    {
+        let m = client.next().await.unwrap().unwrap();
+        serde_json::from_str(m.to_text().unwrap()).unwrap()
     }
 }
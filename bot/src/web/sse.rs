@@ -0,0 +1,123 @@
+use crate::bus;
+use crate::prelude::*;
+use warp::{filters, sse, Filter as _};
+
+/// Streaming a bus as server-sent events.
+///
+/// Every message is sent with its `type` as the event name and the sequence
+/// number up to which every message has been sent as the event id, so that a
+/// reconnecting client which provides a `Last-Event-ID` has the messages it
+/// missed replayed.
+pub(super) fn sse_bus<T>(bus: bus::Bus<T>) -> filters::BoxedFilter<(impl warp::Reply,)>
+where
+    T: bus::Message,
+{
+    sse::last_event_id::<u64>()
+        .map(move |last_event_id: Option<u64>| {
+            let events = events(bus.clone(), last_event_id);
+            sse::reply(sse::keep_alive().stream(events))
+        })
+        .boxed()
+}
+
+/// Stream of events for a single client.
+fn events<T>(
+    bus: bus::Bus<T>,
+    last_event_id: Option<u64>,
+) -> impl stream::Stream<Item = Result<sse::Event, bus::BusError>>
+where
+    T: bus::Message,
+{
+    async_stream::try_stream! {
+        let (seq, snapshot, mut rx) = bus.subscribe_with_snapshot_seq();
+
+        for event in catch_up(&bus, last_event_id, seq, snapshot)? {
+            yield event;
+        }
+
+        loop {
+            let m = match rx.recv().await {
+                Ok(m) => m,
+                Err(bus::BusError::Lagged(n)) => {
+                    log::warn!("bus client lagged behind by {} messages, resyncing", n);
+                    let last_seq = rx.resume_seq();
+                    let (seq, snapshot, new_rx) = bus.subscribe_with_snapshot_seq();
+                    rx = new_rx;
+
+                    for event in catch_up(&bus, Some(last_seq), seq, snapshot)? {
+                        yield event;
+                    }
+
+                    continue;
+                }
+                Err(..) => break,
+            };
+
+            if let Some(event) = event(&bus, Some(rx.resume_seq()), &m)? {
+                yield event;
+            }
+        }
+    }
+}
+
+/// Events bringing a client up to date with the bus as of `seq`.
+///
+/// Messages sent after `last_seq` are replayed if they are still retained.
+/// Otherwise the snapshot of cached messages is sent instead.
+fn catch_up<T>(
+    bus: &bus::Bus<T>,
+    last_seq: Option<u64>,
+    seq: u64,
+    snapshot: Vec<T>,
+) -> Result<Vec<sse::Event>, bus::BusError>
+where
+    T: bus::Message,
+{
+    let mut events = Vec::new();
+
+    match last_seq.and_then(|last_seq| bus.replay(last_seq, seq)) {
+        Some(replay) => {
+            for (seq, m) in replay {
+                events.extend(event(bus, Some(seq), &m)?);
+            }
+        }
+        None => {
+            for m in snapshot {
+                events.extend(event(bus, None, &m)?);
+            }
+        }
+    }
+
+    Ok(events)
+}
+
+/// Convert a bus message into an event.
+///
+/// Messages without a sequence number are cached messages from a snapshot,
+/// and are serialized as replays. Events are always sent as compact JSON,
+/// even if the bus pretty-prints messages.
+///
+/// Returns `None` if the message should be skipped.
+fn event<T>(bus: &bus::Bus<T>, seq: Option<u64>, m: &T) -> Result<Option<sse::Event>, bus::BusError>
+where
+    T: bus::Message,
+{
+    let (ty, data) = match bus.serialize_event(seq.is_none(), m)? {
+        Some(event) => event,
+        None => return Ok(None),
+    };
+
+    let mut event = sse::Event::default();
+
+    // NB: the event name is only a convenience for clients, so messages which
+    // don't carry a tag are still sent.
+    if let Some(ty) = ty {
+        event = event.event(ty);
+    }
+
+    if let Some(seq) = seq {
+        event = event.id(seq.to_string());
+    }
+
+    Ok(Some(event.data(data)))
+}
@@ -0,0 +1,419 @@
+use crate::bus;
+use crate::prelude::*;
+use anyhow::Result;
+use flate2::write::DeflateEncoder;
+use std::borrow::Cow;
+use std::fmt;
+use std::io::Write as _;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tracing::trace_span;
+use tracing_futures::Instrument as _;
+use warp::{filters, Filter as _};
+
+/// Control messages which can be sent by bus clients.
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "type")]
+enum BusControl {
+    /// Resume a previous connection, replaying every message after
+    /// `last_seq` instead of sending a snapshot.
+    ///
+    /// Must be sent within [RESUME_TIMEOUT] of connecting, before the snapshot
+    /// is sent. Later resumes are ignored.
+    ///
+    /// High priority messages can be received ahead of normal ones, so
+    /// `last_seq` must be the sequence number up to which *every* message was
+    /// received. Clients drop replayed messages with a sequence number they've
+    /// already seen.
+    #[serde(rename = "resume")]
+    Resume { token: String, last_seq: u64 },
+    /// Compress every subsequent message with a single deflate stream, sent
+    /// as binary frames.
+    #[serde(rename = "compress")]
+    Compress,
+    /// Only send the fields of keyed messages which changed since the last
+    /// message with the same key.
+    #[serde(rename = "deltas")]
+    Deltas,
+}
+
+/// Events sent to bus clients which are not part of the bus itself.
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "type")]
+enum BusEvent<'a> {
+    /// Sent when a client connects.
+    #[serde(rename = "bus/connected")]
+    Connected { token: &'a str, id: u64, seq: u64 },
+    /// Sent after a full snapshot when a resume was not possible.
+    #[serde(rename = "bus/resynced")]
+    Resynced { seq: u64 },
+    /// Sent uncompressed right before compression is enabled.
+    #[serde(rename = "bus/compressed")]
+    Compressed,
+    /// Sent before the connection is closed because the bus was shut down,
+    /// with how long the client should wait before reconnecting.
+    #[serde(rename = "close")]
+    Close { retry_after_ms: u64 },
+}
+
+/// How long a connecting client has to resume a previous connection before
+/// it's sent a full snapshot.
+const RESUME_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A transform applied to every message before it's sent to a connection.
+pub(super) type Transform<T> = Arc<dyn Fn(&T) -> T + Send + Sync>;
+
+/// Connecting a bus to a websocket connection.
+pub(super) fn send_bus<T>(bus: bus::Bus<T>) -> filters::BoxedFilter<(impl warp::Reply,)>
+where
+    T: bus::Message,
+{
+    send_bus_with(bus, None)
+}
+
+/// Connecting a bus to a websocket connection, rewriting every message with
+/// the given transform before it's sent.
+///
+/// The transform runs once per message for every connection, including for
+/// cached messages which are replayed.
+pub(super) fn send_bus_with<T>(
+    bus: bus::Bus<T>,
+    transform: Option<Transform<T>>,
+) -> filters::BoxedFilter<(impl warp::Reply,)>
+where
+    T: bus::Message,
+{
+    warp::ws()
+        .and(super::remote_addr())
+        .map({
+            move |ws: warp::ws::Ws, addr: Option<SocketAddr>| {
+                let bus = bus.clone();
+                let transform = transform.clone();
+
+                ws.on_upgrade(move |websocket: filters::ws::WebSocket| {
+                    let peer = Peer(addr);
+
+                    let socket = Socket {
+                        bus,
+                        peer,
+                        ws: websocket,
+                        compress: None,
+                        deltas: None,
+                        transform,
+                    };
+
+                    async move {
+                        if let Err(e) = socket.run().await {
+                            log_error!(e, "websocket error (peer: {})", peer);
+                        }
+                    }
+                    .instrument(trace_span!(target: "futures", "bus-client", peer = %peer))
+                })
+            }
+        })
+        .boxed()
+}
+
+/// The address of a connected peer, for diagnostics.
+#[derive(Debug, Clone, Copy)]
+struct Peer(Option<SocketAddr>);
+
+impl fmt::Display for Peer {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(addr) => addr.fmt(fmt),
+            None => "unknown".fmt(fmt),
+        }
+    }
+}
+
+/// A single websocket connection to a bus.
+struct Socket<T>
+where
+    T: bus::Message,
+{
+    bus: bus::Bus<T>,
+    peer: Peer,
+    ws: filters::ws::WebSocket,
+    /// Compressor, if the client asked for compression.
+    compress: Option<DeflateEncoder<Vec<u8>>>,
+    /// Previous values of keyed messages, if the client asked for deltas.
+    deltas: Option<bus::Deltas>,
+    /// Transform applied to every message before it's sent.
+    transform: Option<Transform<T>>,
+}
+
+impl<T> Socket<T>
+where
+    T: bus::Message,
+{
+    /// Forward the bus message to the websocket.
+    async fn run(mut self) -> Result<()> {
+        // add a receiver and forward all new messages.
+        let (seq, snapshot, mut rx) = self.bus.subscribe_with_snapshot_seq();
+        let mut connection = self.bus.connect_from(self.peer.0);
+
+        let token = self.bus.token().to_string();
+        let id = connection.id();
+        self.send_event(&BusEvent::Connected {
+            token: &token,
+            id,
+            seq,
+        })
+        .await?;
+
+        // NB: hold back the snapshot until the client had a chance to resume,
+        // so that a replay isn't sent on top of it.
+        let deadline = tokio::time::Instant::now() + RESUME_TIMEOUT;
+        let mut resume = None;
+
+        while let Ok(m) = tokio::time::timeout_at(deadline, self.ws.next()).await {
+            let control = match m {
+                Some(m) => match parse_control(&m?) {
+                    Some(control) => control,
+                    None => continue,
+                },
+                None => return Ok(()),
+            };
+
+            if let BusControl::Resume { token, last_seq } = control {
+                resume = Some(if token == self.bus.token() {
+                    self.bus.replay(last_seq, seq)
+                } else {
+                    None
+                });
+
+                break;
+            }
+
+            self.control(control).await?;
+        }
+
+        match resume {
+            Some(Some(replay)) => {
+                for (seq, m) in replay {
+                    self.send_message(Some(seq), &m).await?;
+                }
+            }
+            resume => {
+                // send all cached messages.
+                for m in snapshot {
+                    self.send_replay(&m).await?;
+                }
+
+                if resume.is_some() {
+                    self.send_event(&BusEvent::Resynced { seq }).await?;
+                }
+            }
+        }
+
+        loop {
+            tokio::select! {
+                m = rx.recv_seq() => {
+                    let (seq, m) = match m {
+                        Ok(m) => m,
+                        Err(bus::BusError::Lagged(n)) => {
+                            log::warn!("bus client lagged behind by {} messages, resyncing", n);
+                            self.resync(&mut rx).await?;
+                            continue;
+                        }
+                        Err(bus::BusError::Closed) => return self.close().await,
+                        Err(e) => return Err(e.into()),
+                    };
+
+                    self.send_batch(&mut rx, seq, &m).await?;
+                }
+                Some(m) = connection.recv() => {
+                    self.send_message(None, &m).await?;
+                }
+                m = self.ws.next() => {
+                    let m = match m {
+                        Some(m) => m?,
+                        None => return Ok(()),
+                    };
+
+                    if let Some(control) = parse_control(&m) {
+                        self.control(control).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handle a control message sent by a bus client.
+    ///
+    /// Resumes are handled while connecting, so they're ignored here.
+    async fn control(&mut self, control: BusControl) -> Result<()> {
+        match control {
+            BusControl::Resume { .. } => {
+                log::trace!(
+                    "ignoring resume sent after the snapshot (peer: {})",
+                    self.peer
+                );
+            }
+            BusControl::Deltas => {
+                if self.deltas.is_none() {
+                    self.deltas = Some(bus::Deltas::default());
+                }
+            }
+            BusControl::Compress => {
+                if self.compress.is_none() {
+                    self.send_event(&BusEvent::Compressed).await?;
+                    self.compress = Some(DeflateEncoder::new(
+                        Vec::new(),
+                        flate2::Compression::default(),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resubscribe a client which lagged behind and send it a full snapshot,
+    /// so that it's caught up without affecting any other client.
+    async fn resync(&mut self, rx: &mut bus::Reader<T>) -> Result<()> {
+        let (seq, snapshot, new_rx) = self.bus.subscribe_with_snapshot_seq();
+        *rx = new_rx;
+
+        for m in snapshot {
+            self.send_replay(&m).await?;
+        }
+
+        self.send_event(&BusEvent::Resynced { seq }).await?;
+        Ok(())
+    }
+
+    /// Tell the client that the bus was shut down and close the connection.
+    async fn close(&mut self) -> Result<()> {
+        use futures_util::sink::SinkExt;
+
+        let retry_after_ms = self.bus.retry_after().as_millis() as u64;
+        self.send_event(&BusEvent::Close { retry_after_ms }).await?;
+        // NB: `WebSocket::close` consumes the socket, so close it through the
+        // sink instead.
+        SinkExt::close(&mut self.ws).await?;
+        Ok(())
+    }
+
+    /// Send a connection event which is not part of the bus.
+    async fn send_event(&mut self, event: &BusEvent<'_>) -> Result<()> {
+        self.send_text(serde_json::to_string(event)?).await
+    }
+
+    /// Apply the transform of the connection to the given message, if any.
+    fn transform<'a>(&self, m: &'a T) -> Cow<'a, T> {
+        match &self.transform {
+            Some(transform) => Cow::Owned(transform(m)),
+            None => Cow::Borrowed(m),
+        }
+    }
+
+    /// Serialize a bus message, only with the fields which changed if the
+    /// client asked for deltas.
+    fn serialize(&mut self, seq: Option<u64>, m: &T) -> Result<Option<String>> {
+        let m = self.transform(m);
+
+        Ok(match &mut self.deltas {
+            Some(deltas) => self.bus.serialize_delta(deltas, seq, &m)?,
+            None => self.bus.serialize(seq, &m)?,
+        })
+    }
+
+    /// Send a bus message, tagged with its sequence number if present.
+    async fn send_message(&mut self, seq: Option<u64>, m: &T) -> Result<()> {
+        match self.serialize(seq, m)? {
+            Some(text) => self.send_text(text).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Send a cached bus message which is replayed to the client.
+    ///
+    /// Replays are always sent in full.
+    async fn send_replay(&mut self, m: &T) -> Result<()> {
+        let m = self.transform(m);
+
+        if let Some(deltas) = &mut self.deltas {
+            deltas.record(&*m)?;
+        }
+
+        match self.bus.serialize_replay(&m)? {
+            Some(text) => self.send_text(text).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Send a bus message, together with any messages which become ready
+    /// within the configured batch window.
+    ///
+    /// If the client lags behind while the batch is collected, the batch is
+    /// dropped and the client is resynced instead.
+    async fn send_batch(&mut self, rx: &mut bus::Reader<T>, seq: u64, m: &T) -> Result<()> {
+        let window = self.bus.batch_window();
+
+        if window.as_nanos() == 0 {
+            return self.send_message(Some(seq), m).await;
+        }
+
+        let mut batch = self
+            .serialize(Some(seq), m)?
+            .into_iter()
+            .collect::<Vec<_>>();
+        let deadline = tokio::time::Instant::now() + window;
+
+        loop {
+            let (seq, m) = match tokio::time::timeout_at(deadline, rx.recv_seq()).await {
+                Ok(Ok(m)) => m,
+                Ok(Err(bus::BusError::Lagged(n))) => {
+                    log::warn!("bus client lagged behind by {} messages, resyncing", n);
+                    return self.resync(rx).await;
+                }
+                Ok(Err(..)) | Err(..) => break,
+            };
+
+            batch.extend(self.serialize(Some(seq), &m)?);
+        }
+
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        // NB: pretty-printed messages span multiple lines, so they can't be
+        // separated by newlines.
+        let text = bus::frame(&batch, self.bus.pretty());
+        self.send_text(text).await
+    }
+
+    /// Send the given text, compressing it if enabled.
+    async fn send_text(&mut self, text: String) -> Result<()> {
+        use futures_util::sink::SinkExt as _;
+
+        let m = match &mut self.compress {
+            Some(compress) => {
+                compress.write_all(text.as_bytes())?;
+                // NB: a sync flush makes everything written so far decodable
+                // by the client without ending the deflate stream.
+                compress.flush()?;
+                filters::ws::Message::binary(std::mem::take(compress.get_mut()))
+            }
+            None => filters::ws::Message::text(text),
+        };
+
+        self.ws.send(m).await?;
+        Ok(())
+    }
+}
+
+/// Parse a control message sent by a bus client, ignoring anything else.
+fn parse_control(m: &filters::ws::Message) -> Option<BusControl> {
+    let text = m.to_str().ok()?;
+
+    match serde_json::from_str::<BusControl>(text) {
+        Ok(control) => Some(control),
+        Err(e) => {
+            log::trace!("ignoring bad bus control message: {}", e);
+            None
+        }
+    }
+}